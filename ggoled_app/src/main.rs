@@ -2,8 +2,12 @@
 
 mod os;
 
-use chrono::{DateTime, Local, TimeDelta, Timelike};
-use ggoled_draw::{DrawDevice, DrawEvent, LayerId, ShiftMode, TextRenderer, bitmap_from_memory};
+use chrono::{Local, TimeDelta, Timelike};
+use ggoled_draw::{
+    ClockStyle, Direction, Dither, DrawDevice, DrawEvent, DrawLayer, Halo, ImageOptions, Justify, LayerId,
+    LayerOptions, LayerTransaction, Layout, Node, ShiftMode, Size, TextAlign, TextRenderer, TextStyle, Transition,
+    TransitionKind, bitmap_from_memory,
+};
 use ggoled_lib::Device;
 use os::{Media, OSFeatures, OSImpl};
 use rfd::{MessageDialog, MessageLevel};
@@ -20,6 +24,9 @@ use std::{
 };
 
 const NOTIF_DUR: Duration = Duration::from_secs(5);
+const TRANSITION_DUR: Duration = Duration::from_millis(300);
+// Stacking order for overlays that should stay above the time and media
+const OVERLAY_Z: i32 = 10;
 
 #[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
 enum ConfigTimeMode {
@@ -59,13 +66,78 @@ enum StatusNotifyMode {
 #[derive(Serialize, Deserialize, Default)]
 struct ConfigFont {
     path: PathBuf,
-    size: f32,
+    // Only used by TTF and OTF fonts
+    size: Option<f32>,
+}
+
+// A single `[font]`, or a `[[font]]` list where later fonts are used for characters that earlier fonts lack
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ConfigFonts {
+    One(ConfigFont),
+    Chain(Vec<ConfigFont>),
+}
+impl ConfigFonts {
+    fn as_slice(&self) -> &[ConfigFont] {
+        match self {
+            ConfigFonts::One(font) => std::slice::from_ref(font),
+            ConfigFonts::Chain(fonts) => fonts,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+enum ConfigHalo {
+    #[default]
+    None,
+    Outline,
+    Shadow,
+}
+impl ConfigHalo {
+    fn to_api(self) -> Halo {
+        match self {
+            ConfigHalo::None => Halo::None,
+            ConfigHalo::Outline => Halo::Outline,
+            ConfigHalo::Shadow => Halo::Shadow,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigText {
+    halo: ConfigHalo,
+    tracking: i32,
+    line_height: Option<usize>,
+    threshold: f32,
+}
+impl Default for ConfigText {
+    fn default() -> Self {
+        let style = TextStyle::default();
+        Self {
+            halo: ConfigHalo::default(),
+            tracking: style.tracking,
+            line_height: style.line_height,
+            threshold: style.threshold,
+        }
+    }
+}
+impl ConfigText {
+    fn to_api(&self) -> TextStyle {
+        TextStyle {
+            halo: self.halo.to_api(),
+            tracking: self.tracking,
+            line_height: self.line_height,
+            threshold: self.threshold.clamp(0.0, 1.0),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 struct Config {
-    font: Option<ConfigFont>,
+    font: Option<ConfigFonts>,
+    text: ConfigText,
     time_mode: ConfigTimeMode,
     show_media: bool,
     idle_timeout: bool,
@@ -76,6 +148,7 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             font: None,
+            text: ConfigText::default(),
             time_mode: ConfigTimeMode::default(),
             show_media: true,
             idle_timeout: true,
@@ -221,6 +294,26 @@ impl<T: Copy + PartialEq> RadioMenu<T> {
     }
 }
 
+// Parts of the screen that are laid out
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Time,
+    Media,
+}
+
+// Update, add or remove a layer depending on whether it currently exists and should exist
+fn put_layer(tx: &mut LayerTransaction, id: &mut Option<LayerId>, layer: Option<DrawLayer>) {
+    match (*id, layer) {
+        (Some(existing), Some(layer)) => tx.update_layer(existing, layer),
+        (None, Some(layer)) => *id = Some(tx.add_layer(layer)),
+        (Some(existing), None) => {
+            tx.remove_layer(existing);
+            *id = None;
+        }
+        (None, None) => {}
+    }
+}
+
 fn main() {
     // Initial loading
     let mut config = Config::load();
@@ -312,49 +405,63 @@ fn main() {
     bind_menu_event(tm_quit, &menu_tx, MenuEvent::Quit);
 
     // Load icons
+    let icon_opts = ImageOptions {
+        dither: Dither::Threshold(0x80),
+        ..Default::default()
+    };
     let icon_hs_connect =
-        Arc::new(bitmap_from_memory(include_bytes!("../assets/headset_connected.png"), 0x80).unwrap());
+        Arc::new(bitmap_from_memory(include_bytes!("../assets/headset_connected.png"), &icon_opts).unwrap());
     let icon_hs_disconnect =
-        Arc::new(bitmap_from_memory(include_bytes!("../assets/headset_disconnected.png"), 0x80).unwrap());
+        Arc::new(bitmap_from_memory(include_bytes!("../assets/headset_disconnected.png"), &icon_opts).unwrap());
 
     let notif_update = |dev: &mut DrawDevice,
                         layer: &mut Option<LayerId>,
                         mode: StatusNotifyMode,
                         connected: bool,
-                        expiry: DateTime<Local>| {
+                        expiry: Instant| {
+        let mut tx = dev.transaction();
         if let Some(id) = layer.take() {
-            dev.remove_layer(id);
+            tx.remove_layer(id);
         }
         let show = match mode {
             StatusNotifyMode::Off => false,
-            StatusNotifyMode::Notify => Local::now() < expiry,
+            StatusNotifyMode::Notify => Instant::now() < expiry,
             StatusNotifyMode::Always => true,
             StatusNotifyMode::WhenConnected => connected,
             StatusNotifyMode::WhenDisconnected => !connected,
         };
         if show {
             *layer = Some(
-                dev.add_layer(ggoled_draw::DrawLayer::Image {
-                    bitmap: (if connected {
-                        &icon_hs_connect
-                    } else {
-                        &icon_hs_disconnect
-                    })
-                    .clone(),
-                    x: 4,
-                    y: 4,
-                }),
+                tx.add_layer_with(
+                    ggoled_draw::DrawLayer::Image {
+                        bitmap: (if connected {
+                            &icon_hs_connect
+                        } else {
+                            &icon_hs_disconnect
+                        })
+                        .clone(),
+                        x: 4,
+                        y: 4,
+                    },
+                    LayerOptions {
+                        z: OVERLAY_Z,
+                        expiry: (mode == StatusNotifyMode::Notify).then_some(expiry),
+                    },
+                ),
             );
         }
+        tx.commit();
     };
 
     // State
     let mut last_time = Local::now() - TimeDelta::seconds(1);
     let mut last_media: Option<Media> = None;
-    let mut time_layers: Vec<LayerId> = vec![];
-    let mut media_layers: Vec<LayerId> = vec![];
+    let mut was_idle = false;
+    let mut time_layer: Option<LayerId> = None;
+    let mut last_time_format: Option<&str> = None;
+    let mut media_layer: Option<LayerId> = None;
     let mut notif_layer: Option<LayerId> = None;
-    let mut notif_expiry = Local::now();
+    let mut notif_expiry = Instant::now();
     let mut is_connected = false;
 
     // Wait for connect
@@ -381,10 +488,17 @@ fn main() {
     };
     unsafe { sdl::SDL_SetTrayIcon(tray, icon.surf) };
     let mut dev = DrawDevice::new(dev, 30);
-    if let Some(font) = &config.font {
-        dev.texter = dialog_unwrap(TextRenderer::load_from_file(&font.path, font.size));
-    }
+    let texter = config
+        .font
+        .iter()
+        .flat_map(|fonts| fonts.as_slice())
+        .map(|font| dialog_unwrap(TextRenderer::load_from_file(&font.path, font.size)))
+        .reduce(TextRenderer::with_fallback)
+        .unwrap_or_else(TextRenderer::new_pixel_operator);
+    dev.texter = Arc::new(texter.with_style(config.text.to_api()));
+
     dev.probe();
+    let mut layout = Layout::new(Node::column(vec![]), dev.screen_rect());
 
     // Go!
     dev.set_shift_mode(config.oled_shift.to_api());
@@ -441,16 +555,18 @@ fn main() {
             println!("event: {:?}", event);
             match event {
                 DrawEvent::DeviceDisconnected => unsafe { sdl::SDL_SetTrayIcon(tray, icon_error.surf) },
+                DrawEvent::LayerExpired(id) if notif_layer == Some(id) => notif_layer = None,
+                DrawEvent::LayerExpired(_) | DrawEvent::AnimationFinished(_) => {}
                 DrawEvent::DeviceReconnected => {
                     unsafe { sdl::SDL_SetTrayIcon(tray, icon.surf) }
                     dev.probe(); // re-probe when base station reappears
                 }
-                #[allow(clippy::single_match)]
+                #[allow(clippy::single_match, clippy::collapsible_match)]
                 DrawEvent::DeviceEvent(event) => match event {
                     ggoled_lib::DeviceEvent::HeadsetConnection { wireless, .. } => {
                         if wireless != is_connected {
                             is_connected = wireless;
-                            notif_expiry = Local::now() + NOTIF_DUR;
+                            notif_expiry = Instant::now() + NOTIF_DUR;
                             notif_update(
                                 &mut dev,
                                 &mut notif_layer,
@@ -471,18 +587,18 @@ fn main() {
         if time.second() != last_time.second() || force_redraw {
             last_time = time;
 
-            // Remove expired notifications in Notify mode
-            if config.status_notify == StatusNotifyMode::Notify
-                && time >= notif_expiry
-                && let Some(layer) = notif_layer.take()
-            {
-                dev.remove_layer(layer);
-            }
-
             // Check if idle
-            if config.idle_timeout && os.is_idle() {
-                dev.clear_layers(); // clear screen when idle
-                notif_layer = None;
+            let idle = config.idle_timeout && os.is_idle();
+            if idle {
+                // clear screen when idle
+                let mut tx = dev.transaction();
+                tx.clear_layers();
+                if was_idle {
+                    tx.commit();
+                } else {
+                    tx.commit_with_transition(Transition::new(TransitionKind::Dissolve, TRANSITION_DUR));
+                }
+                (notif_layer, time_layer, media_layer) = (None, None, None);
                 last_media = None; // reset media so we check again when not idle
             } else {
                 // Update notifications
@@ -496,37 +612,70 @@ fn main() {
                     );
                 }
 
-                // Fetch media once a second (before pausing screen)
+                // Fetch media once a second
                 let media = if config.show_media { os.get_media() } else { None };
 
-                dev.pause();
-
-                // Time
-                dev.remove_layers(&time_layers);
-                if config.time_mode != ConfigTimeMode::Off {
-                    let time_str = match config.time_mode {
-                        ConfigTimeMode::Off => unreachable!(),
-                        ConfigTimeMode::H24 => time.format("%H:%M:%S").to_string(),
-                        ConfigTimeMode::H12 => time.format("%l:%M:%S %p").to_string(),
+                // Time fills the screen, or sits on top of the media when it's shown
+                let time_format = match config.time_mode {
+                    ConfigTimeMode::Off => None,
+                    ConfigTimeMode::H24 => Some("%H:%M:%S"),
+                    ConfigTimeMode::H12 => Some("%l:%M:%S %p"),
+                };
+                let line_height = dev.font_line_height();
+                let time_height = if media.is_some() {
+                    Size::Fixed(line_height)
+                } else {
+                    Size::Flex(1)
+                };
+                let mut items = vec![];
+                if time_format.is_some() {
+                    items.push(Node::item(Slot::Time, (0, 0)).size(Size::Flex(1), time_height));
+                }
+                if media.is_some() {
+                    items.push(Node::item(Slot::Media, (0, line_height * 2)).width(Size::Flex(1)));
+                }
+                layout.set_root(Node::column(items).justify(Justify::Center));
+                let moved = layout.update();
+                let time_rect = layout.get(&Slot::Time);
+                let media_rect = layout.get(&Slot::Media);
+
+                let media_text = media
+                    .as_ref()
+                    .zip(media_rect)
+                    .map(|(m, rect)| dev.text_layer(&format!("{}\n{}", m.title, m.artist), TextAlign::Center, rect));
+
+                let font = dev.texter.clone();
+                let mut tx = dev.transaction();
+                // The clock updates itself, so it's only replaced when its format changes
+                if time_format != last_time_format || time_layer.is_none() {
+                    let clock = time_format.zip(time_rect).map(|(format, rect)| DrawLayer::Clock {
+                        format: format.to_string(),
+                        font,
+                        style: ClockStyle::Digital,
+                        rect,
+                    });
+                    put_layer(&mut tx, &mut time_layer, clock);
+                    last_time_format = time_format;
+                }
+                // Layers that keep their content are moved when the layout changes around them
+                for (slot, rect) in moved {
+                    let id = match slot {
+                        Slot::Time => time_layer,
+                        Slot::Media => media_layer,
                     };
-                    time_layers = dev.add_text(&time_str, None, if media.is_some() { Some(8) } else { None });
+                    if let Some(id) = id {
+                        tx.set_rect(id, rect);
+                    }
                 }
-
-                // Media
                 if media != last_media {
-                    dev.remove_layers(&media_layers);
-                    if let Some(m) = &media {
-                        media_layers = dev.add_text(
-                            &format!("{}\n{}", m.title, m.artist),
-                            None,
-                            Some(8 + dev.font_line_height() as isize),
-                        );
-                    }
+                    put_layer(&mut tx, &mut media_layer, media_text);
                     last_media = media;
+                    tx.commit_with_transition(Transition::new(TransitionKind::Push(Direction::Up), TRANSITION_DUR));
+                } else {
+                    tx.commit();
                 }
-
-                dev.play();
             }
+            was_idle = idle;
         }
 
         sleep(Duration::from_millis(10));
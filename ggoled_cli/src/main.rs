@@ -1,20 +1,32 @@
 use clap::{Parser, ValueEnum};
 use core::str;
+use ggoled_draw::Dither;
 use ggoled_draw::DrawDevice;
+use ggoled_draw::FrameStream;
+use ggoled_draw::ImageOptions;
+use ggoled_draw::LayerTransaction;
 use ggoled_draw::bitmap_from_memory;
 use ggoled_draw::decode_frames;
+use ggoled_draw::{Anchor, FilterType, Fit};
+use ggoled_draw::{Halo, TextAlign, TextOptions, TextRenderer, TextStyle, Wrap};
+use ggoled_draw::{ScrollMode, ScrollParams};
 use ggoled_lib::Bitmap;
 use ggoled_lib::Device;
 use spin_sleep::sleep;
 use std::sync::Arc;
 use std::time::Instant;
 use std::{
+    collections::HashMap,
     io::{Read, stdin},
     ops::Div,
+    path::PathBuf,
     str::FromStr,
     time::Duration,
 };
 
+// Frames decoded ahead of playback in `anim`
+const ANIM_READ_AHEAD: usize = 64;
+
 #[derive(Clone, Copy)]
 enum DrawPos {
     Coord(isize),
@@ -49,6 +61,50 @@ enum Alignment {
     Center,
     #[value(alias("r"))]
     Right,
+    #[value(alias("j"))]
+    Justify,
+}
+impl Alignment {
+    fn to_api(self) -> TextAlign {
+        match self {
+            Alignment::Left => TextAlign::Left,
+            Alignment::Center => TextAlign::Center,
+            Alignment::Right => TextAlign::Right,
+            Alignment::Justify => TextAlign::Justify,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum WrapMode {
+    None,
+    Word,
+    Char,
+}
+impl WrapMode {
+    fn to_api(self) -> Wrap {
+        match self {
+            WrapMode::None => Wrap::None,
+            WrapMode::Word => Wrap::Word,
+            WrapMode::Char => Wrap::Char,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HaloMode {
+    None,
+    Outline,
+    Shadow,
+}
+impl HaloMode {
+    fn to_api(self) -> Halo {
+        match self {
+            HaloMode::None => Halo::None,
+            HaloMode::Outline => Halo::Outline,
+            HaloMode::Shadow => Halo::Shadow,
+        }
+    }
 }
 
 #[derive(Clone, Copy, ValueEnum)]
@@ -57,6 +113,119 @@ enum ScrollSpeed {
     Normal,
     Fast,
 }
+impl ScrollSpeed {
+    fn px_per_sec(self) -> f32 {
+        match self {
+            ScrollSpeed::Slow => 15.0,
+            ScrollSpeed::Normal => 30.0,
+            ScrollSpeed::Fast => 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DitherMode {
+    None,
+    Otsu,
+    #[value(alias("fs"))]
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    BlueNoise,
+}
+impl DitherMode {
+    fn to_api(self, threshold: u8) -> Dither {
+        match self {
+            DitherMode::None => Dither::Threshold(threshold),
+            DitherMode::Otsu => Dither::Otsu,
+            DitherMode::FloydSteinberg => Dither::FloydSteinberg,
+            DitherMode::Atkinson => Dither::Atkinson,
+            DitherMode::Sierra => Dither::Sierra,
+            DitherMode::Bayer2 => Dither::Bayer(2),
+            DitherMode::Bayer4 => Dither::Bayer(4),
+            DitherMode::Bayer8 => Dither::Bayer(8),
+            DitherMode::BlueNoise => Dither::BlueNoise,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FitMode {
+    None,
+    Contain,
+    Cover,
+    Stretch,
+}
+impl FitMode {
+    fn to_api(self) -> Fit {
+        match self {
+            FitMode::None => Fit::None,
+            FitMode::Contain => Fit::Contain,
+            FitMode::Cover => Fit::Cover,
+            FitMode::Stretch => Fit::Stretch,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterMode {
+    Nearest,
+    Linear,
+    Cubic,
+    Gaussian,
+    Lanczos,
+}
+impl FilterMode {
+    fn to_api(self) -> FilterType {
+        match self {
+            FilterMode::Nearest => FilterType::Nearest,
+            FilterMode::Linear => FilterType::Triangle,
+            FilterMode::Cubic => FilterType::CatmullRom,
+            FilterMode::Gaussian => FilterType::Gaussian,
+            FilterMode::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AnchorMode {
+    #[value(alias("tl"))]
+    TopLeft,
+    #[value(alias("t"))]
+    Top,
+    #[value(alias("tr"))]
+    TopRight,
+    #[value(alias("l"))]
+    Left,
+    #[value(alias("c"))]
+    Center,
+    #[value(alias("r"))]
+    Right,
+    #[value(alias("bl"))]
+    BottomLeft,
+    #[value(alias("b"))]
+    Bottom,
+    #[value(alias("br"))]
+    BottomRight,
+}
+impl AnchorMode {
+    fn to_api(self) -> Anchor {
+        match self {
+            AnchorMode::TopLeft => Anchor::TopLeft,
+            AnchorMode::Top => Anchor::Top,
+            AnchorMode::TopRight => Anchor::TopRight,
+            AnchorMode::Left => Anchor::Left,
+            AnchorMode::Center => Anchor::Center,
+            AnchorMode::Right => Anchor::Right,
+            AnchorMode::BottomLeft => Anchor::BottomLeft,
+            AnchorMode::Bottom => Anchor::Bottom,
+            AnchorMode::BottomRight => Anchor::BottomRight,
+        }
+    }
+}
 
 #[derive(clap::Args)]
 struct DrawArgs {
@@ -90,10 +259,71 @@ struct ImageArgs {
     )]
     threshold: u8,
 
+    #[arg(
+        short = 'D',
+        long,
+        help = "Dithering mode for converting images to 1-bit - `none` uses the threshold",
+        default_value = "none"
+    )]
+    dither: DitherMode,
+
+    #[arg(
+        short = 'F',
+        long,
+        help = "How to fit images into the screen before converting",
+        default_value = "none"
+    )]
+    fit: FitMode,
+
+    #[arg(long, help = "Resampling filter used when scaling images", default_value = "linear")]
+    filter: FilterMode,
+
+    #[arg(
+        short = 'A',
+        long,
+        help = "Which part of the image to keep when cropping, or where to place it when padding. Images that aren't \
+                scaled are only cropped to the screen when this is given [default: center]"
+    )]
+    anchor: Option<AnchorMode>,
+
+    #[arg(long, help = "Gamma correction applied before converting", default_value = "1.0")]
+    gamma: f32,
+
+    #[arg(long, help = "Contrast multiplier applied before converting", default_value = "1.0")]
+    contrast: f32,
+
+    #[arg(
+        long,
+        help = "Brightness offset applied before converting, -1.0 to 1.0",
+        default_value = "0.0",
+        allow_negative_numbers = true
+    )]
+    brightness: f32,
+
+    #[arg(short = 'I', long, help = "Invert images before converting")]
+    invert: bool,
+
     #[arg(short = 'C', long, help = "Clear the screen before drawing")]
     clear: bool,
 }
 
+impl ImageArgs {
+    fn options(&self, dev: &Device) -> ImageOptions {
+        ImageOptions {
+            dither: self.dither.to_api(self.threshold),
+            fit: self.fit.to_api(),
+            filter: self.filter.to_api(),
+            anchor: self.anchor.map(AnchorMode::to_api),
+            width: dev.width as u32,
+            height: dev.height as u32,
+            gamma: self.gamma,
+            contrast: self.contrast,
+            brightness: self.brightness,
+            invert: self.invert,
+        }
+    }
+}
+
 #[derive(Parser)]
 enum Args {
     #[command(about = "Clear the entire screen to black")]
@@ -115,6 +345,83 @@ enum Args {
 
         #[arg(short = 'd', long, help = "Screen delimiter line for stdin input")]
         delimiter: Option<String>,
+
+        #[arg(
+            short = 's',
+            long,
+            value_enum,
+            default_value = "normal",
+            help = "Speed of lines that don't fit"
+        )]
+        scroll_speed: ScrollSpeed,
+
+        #[arg(long, help = "Scroll lines that don't fit back and forth instead of looping")]
+        bounce: bool,
+
+        #[arg(long, default_value = "0", help = "Seconds to pause before each scroll cycle")]
+        scroll_pause: f32,
+
+        #[arg(short = 'a', long, value_enum, default_value = "center", help = "Alignment of lines")]
+        align: Alignment,
+
+        #[arg(
+            short = 'w',
+            long,
+            value_enum,
+            default_value = "none",
+            help = "Wrap lines that don't fit instead of scrolling them"
+        )]
+        wrap: WrapMode,
+
+        #[arg(
+            long,
+            help = "Limit the number of lines, ending with an ellipsis if there's more text"
+        )]
+        max_lines: Option<usize>,
+
+        #[arg(
+            short = 'f',
+            long,
+            help = "TTF, OTF, BDF or PCF font to use instead of the default. Repeat for fallback fonts."
+        )]
+        font: Vec<PathBuf>,
+
+        #[arg(long, help = "Size of TTF and OTF fonts [default: 16]")]
+        font_size: Option<f32>,
+
+        #[arg(
+            short = 'm',
+            long,
+            conflicts_with_all = ["wrap", "max_lines", "halo"],
+            help = "Style text with [b]bold[/b], [inv]inverted[/inv], [u]underlined[/u] and [size=24]sized[/size] spans"
+        )]
+        markup: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "none",
+            help = "Border around text that clears what's below it"
+        )]
+        halo: HaloMode,
+
+        #[arg(
+            long,
+            default_value = "0",
+            allow_hyphen_values = true,
+            help = "Extra pixels between characters"
+        )]
+        tracking: i32,
+
+        #[arg(long, help = "Height of lines in pixels [default: fits the font]")]
+        line_height: Option<usize>,
+
+        #[arg(
+            long,
+            default_value = "0.5",
+            help = "Coverage from 0 to 1 above which pixels of TTF and OTF glyphs are lit"
+        )]
+        threshold: f32,
     },
 
     #[command(about = "Draw an image")]
@@ -185,11 +492,58 @@ fn main() {
             text,
             draw_args,
             delimiter,
+            scroll_speed,
+            bounce,
+            scroll_pause,
+            align,
+            wrap,
+            max_lines,
+            font,
+            font_size,
+            markup,
+            halo,
+            tracking,
+            line_height,
+            threshold,
         } => {
+            let texter = font
+                .iter()
+                .map(|path| TextRenderer::load_from_file(path, font_size).expect("Failed to load font"))
+                .reduce(TextRenderer::with_fallback)
+                .unwrap_or_else(TextRenderer::new_pixel_operator);
             let mut dev = DrawDevice::new(dev, 30);
+            dev.texter = Arc::new(texter.with_style(TextStyle {
+                halo: halo.to_api(),
+                tracking,
+                line_height,
+                threshold: threshold.clamp(0.0, 1.0),
+            }));
+            dev.scroll = ScrollParams {
+                speed: scroll_speed.px_per_sec(),
+                mode: if bounce { ScrollMode::Bounce } else { ScrollMode::Loop },
+                pause: Duration::from_secs_f32(scroll_pause.max(0.0)),
+                ..Default::default()
+            };
+            dev.text = TextOptions {
+                width: None,
+                wrap: wrap.to_api(),
+                align: align.to_api(),
+                max_lines,
+            };
+            let (x, y) = (draw_args.screen_x.to_option(), draw_args.screen_y.to_option());
+            let add_text = |tx: &mut LayerTransaction, text: &str| {
+                if !markup {
+                    tx.add_text(text, x, y);
+                } else if let Err(err) = tx.add_markup(text, &HashMap::new(), x, y) {
+                    eprintln!("Invalid markup: {err}");
+                    std::process::exit(1);
+                }
+            };
             // TODO: oneshot text should not try scrolling
             if let Some(text) = text {
-                dev.add_text(&text, draw_args.screen_x.to_option(), draw_args.screen_y.to_option());
+                let mut tx = dev.transaction();
+                add_text(&mut tx, &text);
+                tx.commit();
                 dev.play();
             } else {
                 dev.play();
@@ -198,24 +552,20 @@ fn main() {
                 for line in stdin().lines() {
                     let line = line.expect("Failed to read from stdin").replace('\r', "");
                     if Some(&line) == delimiter.as_ref() {
-                        dev.clear_layers();
-                        dev.add_text(
-                            &lines.join("\n"),
-                            draw_args.screen_x.to_option(),
-                            draw_args.screen_y.to_option(),
-                        );
+                        let mut tx = dev.transaction();
+                        tx.clear_layers();
+                        add_text(&mut tx, &lines.join("\n"));
+                        tx.commit();
                         lines.clear();
                     } else {
                         lines.push(line);
                     }
                 }
                 if !lines.is_empty() {
-                    dev.clear_layers();
-                    dev.add_text(
-                        &lines.join("\n"),
-                        draw_args.screen_x.to_option(),
-                        draw_args.screen_y.to_option(),
-                    );
+                    let mut tx = dev.transaction();
+                    tx.clear_layers();
+                    add_text(&mut tx, &lines.join("\n"));
+                    tx.commit();
                 }
             }
         }
@@ -223,9 +573,12 @@ fn main() {
             let bitmap = if path == "-" {
                 let mut buf = Vec::<u8>::new();
                 stdin().read_to_end(&mut buf).expect("Failed to read from stdin");
-                Arc::new(bitmap_from_memory(&buf, image_args.threshold).expect("Failed to read image from stdin"))
+                Arc::new(bitmap_from_memory(&buf, &image_args.options(&dev)).expect("Failed to read image from stdin"))
             } else {
-                let mut frames = decode_frames(&path, image_args.threshold);
+                let mut frames = decode_frames(&path, &image_args.options(&dev)).unwrap_or_else(|err| {
+                    eprintln!("{err:#}");
+                    std::process::exit(1);
+                });
                 if frames.is_empty() {
                     eprintln!("No frames in image");
                     std::process::exit(1);
@@ -260,21 +613,17 @@ fn main() {
                 std::process::exit(1);
             }
             let period = framerate.map(|f| Duration::from_secs(1).div(f));
-            let bitmaps: Vec<(Arc<Bitmap>, Duration)> = paths
-                .iter()
-                .flat_map(|path| {
-                    decode_frames(path, image_args.threshold).into_iter().map(|frame| {
-                        (
-                            frame.bitmap,
-                            period.unwrap_or(frame.delay.unwrap_or(Duration::from_secs(1))),
-                        )
-                    })
-                })
-                .collect();
-            let draw_animation = || {
-                for (bitmap, delay) in &bitmaps {
+            let mut stream = FrameStream::open(paths, &image_args.options(&dev), ANIM_READ_AHEAD);
+            let draw_animation = |stream: &mut FrameStream| {
+                for frame in stream {
+                    let frame = frame.unwrap_or_else(|err| {
+                        eprintln!("{err:#}");
+                        std::process::exit(1);
+                    });
+                    let bitmap = &frame.bitmap;
+                    let delay = period.unwrap_or(frame.delay.unwrap_or(Duration::from_secs(1)));
                     let now_time = Instant::now();
-                    let next_frame = now_time + *delay;
+                    let next_frame = now_time + delay;
                     let cx = (dev.width as isize - bitmap.w as isize) / 2;
                     let cy = (dev.height as isize - bitmap.h as isize) / 2;
                     let x = image_args.draw_args.screen_x.to_option().unwrap_or(cx);
@@ -295,11 +644,15 @@ fn main() {
             };
             if loops == 0 {
                 loop {
-                    draw_animation();
+                    draw_animation(&mut stream);
+                    stream.restart();
                 }
             } else {
-                for _ in 0..loops {
-                    draw_animation();
+                for i in 0..loops {
+                    if i != 0 {
+                        stream.restart();
+                    }
+                    draw_animation(&mut stream);
                 }
             }
         }
@@ -0,0 +1,273 @@
+// Frame scheduling of animation layers.
+
+use crate::{Frame, FrameSource};
+use std::{
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(10);
+// Catching up stops and the schedule is reset when falling further behind than this
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+/// Order in which frames are played. Streams always play forward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Playback {
+    #[default]
+    Forward,
+    Reverse,
+    /// Forward and then backward, without repeating the first and last frame
+    PingPong,
+}
+
+/// What to do when frames are due faster than they can be shown, e.g. when delays are shorter than the frame time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Show every frame, playing faster until back on schedule
+    CatchUp,
+    /// Skip frames to stay on schedule
+    #[default]
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct AnimParams {
+    /// Times to play the animation before stopping on the last frame, or 0 to loop forever
+    pub loops: usize,
+    pub playback: Playback,
+    pub lag: LagPolicy,
+}
+
+fn frame_delay(frame: &Frame) -> Option<Duration> {
+    frame.delay.map(|delay| delay.max(MIN_FRAME_DELAY))
+}
+
+enum Advance {
+    Frame,
+    Pending,
+    Finished,
+}
+
+pub(crate) struct AnimState {
+    /// Position in the current loop. Unused for streams.
+    step: usize,
+    loops_done: usize,
+    // When the next frame is due, relative to the previous due time so that the schedule doesn't drift
+    next_update: Option<Instant>,
+    /// Frame currently shown
+    pub current: Option<Frame>,
+    pub paused: bool,
+    finished: bool,
+    // Stream frames to discard after seeking
+    skip: usize,
+}
+impl AnimState {
+    pub fn new() -> Self {
+        Self {
+            step: 0,
+            loops_done: 0,
+            next_update: None,
+            current: None,
+            paused: false,
+            finished: false,
+            skip: 0,
+        }
+    }
+
+    fn sequence_len(frames: usize, playback: Playback) -> usize {
+        match playback {
+            Playback::PingPong => (frames * 2).saturating_sub(2).max(frames.min(1)),
+            _ => frames,
+        }
+    }
+
+    fn frame_index(&self, frames: usize, playback: Playback) -> usize {
+        let step = self.step % Self::sequence_len(frames, playback).max(1);
+        match playback {
+            Playback::Forward => step,
+            Playback::Reverse => frames - 1 - step,
+            Playback::PingPong if step < frames => step,
+            Playback::PingPong => frames * 2 - 2 - step,
+        }
+    }
+
+    /// Frame to show before the layer has been rendered, for `LayerInfo`.
+    pub fn peek<'a>(&'a self, frames: &'a FrameSource, params: &AnimParams) -> Option<&'a Frame> {
+        match (&self.current, frames) {
+            (Some(frame), _) => Some(frame),
+            (None, FrameSource::Frames(frames)) if !frames.is_empty() => {
+                Some(&frames[self.frame_index(frames.len(), params.playback)])
+            }
+            _ => None,
+        }
+    }
+
+    /// Jump to a frame of the source and play from there, restarting the loop count.
+    /// Streams are restarted and decode their way up to the frame.
+    pub fn seek(&mut self, frames: &mut FrameSource, params: &AnimParams, frame: usize) {
+        match frames {
+            FrameSource::Frames(frames) => {
+                let n = frames.len().max(1);
+                let frame = frame.min(n - 1);
+                self.step = match params.playback {
+                    Playback::Reverse => n - 1 - frame,
+                    _ => frame,
+                };
+            }
+            FrameSource::Stream(stream) => {
+                stream.restart();
+                self.skip = frame;
+            }
+        }
+        self.loops_done = 0;
+        self.finished = false;
+        self.current = None;
+        self.next_update = None;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            // Show the current frame for its full delay again
+            self.next_update = None;
+        }
+        self.paused = paused;
+    }
+
+    // Load the frame at the current position
+    fn load(&mut self, frames: &mut FrameSource, params: &AnimParams) -> bool {
+        match frames {
+            FrameSource::Frames(frames) if !frames.is_empty() => {
+                self.current = Some(frames[self.frame_index(frames.len(), params.playback)].clone());
+                true
+            }
+            FrameSource::Frames(_) => false,
+            FrameSource::Stream(_) => matches!(self.next_stream_frame(frames, params), Advance::Frame),
+        }
+    }
+
+    fn next_stream_frame(&mut self, frames: &mut FrameSource, params: &AnimParams) -> Advance {
+        let FrameSource::Stream(stream) = frames else {
+            unreachable!()
+        };
+        loop {
+            match stream.try_next_frame() {
+                Poll::Pending => return Advance::Pending,
+                Poll::Ready(Some(_)) if self.skip > 0 => self.skip -= 1,
+                Poll::Ready(Some(frame)) => {
+                    self.current = Some(frame);
+                    return Advance::Frame;
+                }
+                // Streams that fail before yielding a single frame are not restarted
+                Poll::Ready(None) if !stream.yielded() => return Advance::Finished,
+                Poll::Ready(None) => {
+                    self.loops_done += 1;
+                    if params.loops != 0 && self.loops_done >= params.loops {
+                        return Advance::Finished;
+                    }
+                    stream.restart();
+                    return Advance::Pending;
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, frames: &mut FrameSource, params: &AnimParams) -> Advance {
+        let n = match frames {
+            FrameSource::Frames(frames) => frames.len(),
+            FrameSource::Stream(_) => return self.next_stream_frame(frames, params),
+        };
+        if n == 0 {
+            return Advance::Pending;
+        }
+        let len = Self::sequence_len(n, params.playback);
+        if self.step + 1 >= len {
+            self.loops_done += 1;
+            if params.loops != 0 && self.loops_done >= params.loops {
+                return Advance::Finished;
+            }
+            self.step = 0;
+        } else {
+            self.step += 1;
+        }
+        self.load(frames, params);
+        Advance::Frame
+    }
+
+    /// Advance the animation to the frame that should be shown at `time`. Returns true when playback just finished.
+    pub fn update(&mut self, frames: &mut FrameSource, params: &AnimParams, follow_fps: bool, time: Instant) -> bool {
+        if self.current.is_none() {
+            // The first frame is shown for at least one render frame
+            if self.load(frames, params) {
+                self.next_update = self.current.as_ref().and_then(frame_delay).map(|delay| time + delay);
+            }
+            return false;
+        }
+        if self.paused || self.finished {
+            return false;
+        }
+        let Some(delay) = self.current.as_ref().and_then(frame_delay).filter(|_| !follow_fps) else {
+            // Frames without a delay advance every render frame
+            return match self.advance(frames, params) {
+                Advance::Finished => {
+                    self.finished = true;
+                    true
+                }
+                _ => false,
+            };
+        };
+        let mut due = *self.next_update.get_or_insert(time + delay);
+        while time >= due {
+            match self.advance(frames, params) {
+                Advance::Frame => {}
+                Advance::Pending => break,
+                Advance::Finished => {
+                    self.finished = true;
+                    return true;
+                }
+            }
+            let delay = self.current.as_ref().and_then(frame_delay).unwrap_or(MIN_FRAME_DELAY);
+            due += delay;
+            if time.saturating_duration_since(due) > MAX_LAG {
+                due = time + delay;
+            }
+            if params.lag == LagPolicy::CatchUp {
+                break;
+            }
+        }
+        self.next_update = Some(due);
+        false
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn skip_and_finish() {
+    let mut frames = FrameSource::Frames(
+        (1..=3)
+            .map(|w| Frame {
+                bitmap: std::sync::Arc::new(ggoled_lib::Bitmap::new(w, 1, false)),
+                delay: Some(Duration::from_millis(100)),
+            })
+            .collect(),
+    );
+    let params = AnimParams {
+        loops: 2,
+        playback: Playback::PingPong,
+        lag: LagPolicy::Skip,
+    };
+    let t0 = Instant::now();
+    let at = |ms| t0 + Duration::from_millis(ms);
+    let mut state = AnimState::new();
+    let mut shown = |state: &mut AnimState, ms| {
+        let finished = state.update(&mut frames, &params, false, at(ms));
+        (state.current.as_ref().unwrap().bitmap.w, finished)
+    };
+    assert_eq!(shown(&mut state, 0), (1, false));
+    assert_eq!(shown(&mut state, 150), (2, false));
+    // Falling behind skips to the frame that is due
+    assert_eq!(shown(&mut state, 420), (1, false));
+    assert_eq!(shown(&mut state, 500), (2, false));
+    assert_eq!(shown(&mut state, 790), (2, false));
+    assert_eq!(shown(&mut state, 800), (2, true));
+    assert_eq!(shown(&mut state, 2000), (2, false));
+}
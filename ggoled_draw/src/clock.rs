@@ -0,0 +1,135 @@
+// Clock layers, which the render thread updates as soon as the shown time changes.
+
+use chrono::{DateTime, Local, Timelike};
+use ggoled_lib::Bitmap;
+use std::{f32::consts::TAU, fmt::Write, time::Duration};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ClockStyle {
+    /// The time as text, formatted with the layer's strftime format. Invalid formats are shown as they are.
+    #[default]
+    Digital,
+    /// A clock face filling the layer box. The format is unused.
+    Analog { seconds: bool },
+}
+
+// Whether a strftime format includes the seconds
+fn shows_seconds(format: &str) -> bool {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        // Skip padding flags and widths, e.g. `%-S` or `%3f`
+        let spec = chars
+            .by_ref()
+            .find(|c| !matches!(c, '-' | '_' | '0'..='9' | '^' | '#' | '.' | ':'));
+        if matches!(spec, Some('S' | 'T' | 'X' | 'r' | 's' | 'c' | '+' | 'f')) {
+            return true;
+        }
+    }
+    false
+}
+
+fn tick_seconds(format: &str, style: ClockStyle) -> i64 {
+    let seconds = match style {
+        ClockStyle::Digital => shows_seconds(format),
+        ClockStyle::Analog { seconds } => seconds,
+    };
+    if seconds { 1 } else { 60 }
+}
+
+/// Time until the shown time next changes.
+pub(crate) fn until_next_tick(format: &str, style: ClockStyle, now: DateTime<Local>) -> Duration {
+    let period = tick_seconds(format, style) * 1000;
+    Duration::from_millis((period - now.timestamp_millis().rem_euclid(period)) as u64)
+}
+
+fn analog_face(w: usize, h: usize, now: DateTime<Local>, seconds: bool) -> Bitmap {
+    let mut face = Bitmap::new(w, h, false);
+    let r = (w.min(h) as isize - 1) / 2;
+    let (cx, cy) = ((w as isize - 1) / 2, (h as isize - 1) / 2);
+    let point = |turns: f32, len: f32| {
+        let angle = turns * TAU;
+        (
+            cx + (angle.sin() * len * r as f32).round() as isize,
+            cy - (angle.cos() * len * r as f32).round() as isize,
+        )
+    };
+    face.draw_circle(cx, cy, r, true);
+    for hour in 0..12 {
+        let (x0, y0) = point(hour as f32 / 12.0, 0.8);
+        let (x1, y1) = point(hour as f32 / 12.0, 1.0);
+        face.draw_line(x0, y0, x1, y1, true);
+    }
+    let minutes = now.minute() as f32 + if seconds { now.second() as f32 / 60.0 } else { 0.0 };
+    let mut hands = vec![
+        ((now.hour12().1 % 12) as f32 / 12.0 + minutes / 720.0, 0.45),
+        (minutes / 60.0, 0.7),
+    ];
+    if seconds {
+        hands.push((now.second() as f32 / 60.0, 0.85));
+    }
+    for (turns, len) in hands {
+        let (x, y) = point(turns, len);
+        face.draw_line(cx, cy, x, y, true);
+    }
+    face
+}
+
+pub(crate) struct ClockState {
+    // Tick, format, style and size that `text` and `face` were made for
+    key: Option<(i64, String, ClockStyle, (usize, usize))>,
+    pub text: String,
+    pub face: Option<Bitmap>,
+}
+impl ClockState {
+    pub fn new() -> Self {
+        Self {
+            key: None,
+            text: String::new(),
+            face: None,
+        }
+    }
+
+    pub fn update(&mut self, format: &str, style: ClockStyle, size: (usize, usize), now: DateTime<Local>) {
+        let tick = now.timestamp().div_euclid(tick_seconds(format, style));
+        if let Some(key) = &self.key
+            && (key.0, key.1.as_str(), key.2, key.3) == (tick, format, style, size)
+        {
+            return;
+        }
+        self.key = Some((tick, format.to_string(), style, size));
+        match style {
+            ClockStyle::Digital => {
+                self.text.clear();
+                if write!(self.text, "{}", now.format(format)).is_err() {
+                    self.text = format.to_string();
+                }
+            }
+            ClockStyle::Analog { seconds } => self.face = Some(analog_face(size.0, size.1, now, seconds)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn clock_ticks() {
+    assert!(shows_seconds("%H:%M:%S"));
+    assert!(shows_seconds("%-I:%M:%S %p"));
+    assert!(shows_seconds("%T"));
+    assert!(!shows_seconds("%H:%M"));
+    assert!(!shows_seconds("100%% %H"));
+    let now = chrono::TimeZone::timestamp_millis_opt(&Local, 1_700_000_012_250).unwrap();
+    assert_eq!(
+        until_next_tick("%S", ClockStyle::Digital, now),
+        Duration::from_millis(750)
+    );
+    assert_eq!(
+        until_next_tick("%M", ClockStyle::Digital, now),
+        Duration::from_millis(27_750)
+    );
+    let mut state = ClockState::new();
+    state.update("%Q", ClockStyle::Digital, (0, 0), now);
+    assert_eq!(state.text, "%Q");
+}
@@ -0,0 +1,217 @@
+// Preparation of images (fitting, scaling and adjustments) before they are converted to bitmaps.
+
+use crate::dither::{self, Dither, Luma};
+use crate::gray::GrayBitmap;
+use ggoled_lib::Bitmap;
+use image::{DynamicImage, RgbaImage, imageops};
+
+pub use image::imageops::FilterType;
+
+/// How images are fit into the target box.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Fit {
+    /// Keep the original size, only cropping anything outside the box when an anchor is given
+    #[default]
+    None,
+    /// Scale to fit entirely inside the box, padding the rest
+    Contain,
+    /// Scale to cover the entire box, cropping the overflow
+    Cover,
+    /// Scale to the box size, ignoring aspect ratio
+    Stretch,
+}
+
+/// Which part of the image is kept when cropping, or where it's placed when padding.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+impl Anchor {
+    fn factors(self) -> (f32, f32) {
+        let fx = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0.0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => 0.5,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => 1.0,
+        };
+        let fy = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0.0,
+            Anchor::Left | Anchor::Center | Anchor::Right => 0.5,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => 1.0,
+        };
+        (fx, fy)
+    }
+    // Offset of an inner size within an outer size. Negative if inner is larger.
+    fn offset(self, outer: (u32, u32), inner: (u32, u32)) -> (i64, i64) {
+        let (fx, fy) = self.factors();
+        (
+            ((outer.0 as f32 - inner.0 as f32) * fx).round() as i64,
+            ((outer.1 as f32 - inner.1 as f32) * fy).round() as i64,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageOptions {
+    pub dither: Dither,
+    pub fit: Fit,
+    pub filter: FilterType,
+    /// Which part of the image is kept when cropping, or where it's placed when padding. Unscaled images are only
+    /// cropped when this is set, and scaled images are centered if unset.
+    pub anchor: Option<Anchor>,
+    /// Size of the box that images are fit into, normally the screen size
+    pub width: u32,
+    pub height: u32,
+    pub gamma: f32,
+    pub contrast: f32,
+    /// Added to the luminance, in the range -1.0 to 1.0
+    pub brightness: f32,
+    pub invert: bool,
+}
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            dither: Dither::Threshold(0x80),
+            fit: Fit::None,
+            filter: FilterType::Triangle,
+            anchor: None,
+            width: 128,
+            height: 64,
+            gamma: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            invert: false,
+        }
+    }
+}
+
+// Image scaled or cropped for the box, or `None` if it's used as it is. Images that are contained are not padded
+// yet, so that adjustments don't apply to the padding.
+fn fit_image(img: &RgbaImage, opts: &ImageOptions) -> Option<RgbaImage> {
+    let (w, h) = img.dimensions();
+    let (bw, bh) = (opts.width.max(1), opts.height.max(1));
+    let anchor = opts.anchor.unwrap_or_default();
+    let scale = |sw: u32, sh: u32| imageops::resize(img, sw.max(1), sh.max(1), opts.filter);
+    let crop = |img: &RgbaImage| {
+        let (iw, ih) = img.dimensions();
+        let (cw, ch) = (iw.min(bw), ih.min(bh));
+        let (ox, oy) = anchor.offset((iw, ih), (cw, ch));
+        imageops::crop_imm(img, ox as u32, oy as u32, cw, ch).to_image()
+    };
+    match opts.fit {
+        Fit::None if opts.anchor.is_none() || (w <= bw && h <= bh) => None,
+        Fit::None => Some(crop(img)),
+        Fit::Stretch => Some(scale(bw, bh)),
+        Fit::Contain => {
+            let ratio = (bw as f32 / w as f32).min(bh as f32 / h as f32);
+            Some(scale(
+                (w as f32 * ratio).round() as u32,
+                (h as f32 * ratio).round() as u32,
+            ))
+        }
+        Fit::Cover => {
+            let ratio = (bw as f32 / w as f32).max(bh as f32 / h as f32);
+            let scaled = scale((w as f32 * ratio).round() as u32, (h as f32 * ratio).round() as u32);
+            Some(crop(&scaled))
+        }
+    }
+}
+
+fn adjust(luma: &mut Luma, opts: &ImageOptions) {
+    if opts.gamma == 1.0 && opts.contrast == 1.0 && opts.brightness == 0.0 && !opts.invert {
+        return;
+    }
+    for v in luma.data.iter_mut() {
+        let mut f = *v / 255.0;
+        f = (f - 0.5) * opts.contrast + 0.5 + opts.brightness;
+        f = f.clamp(0.0, 1.0).powf(1.0 / opts.gamma.max(0.01));
+        if opts.invert {
+            f = 1.0 - f;
+        }
+        *v = f * 255.0;
+    }
+}
+
+// Pad a contained image to the box size with black
+fn letterbox(luma: Luma, opts: &ImageOptions) -> Luma {
+    let (bw, bh) = (opts.width.max(1) as usize, opts.height.max(1) as usize);
+    if (luma.w, luma.h) == (bw, bh) {
+        return luma;
+    }
+    let anchor = opts.anchor.unwrap_or_default();
+    let (ox, oy) = anchor.offset((bw as u32, bh as u32), (luma.w as u32, luma.h as u32));
+    let mut data = vec![0.0; bw * bh];
+    for y in 0..luma.h {
+        for x in 0..luma.w {
+            let (px, py) = (x as i64 + ox, y as i64 + oy);
+            if (0..bw as i64).contains(&px) && (0..bh as i64).contains(&py) {
+                data[px as usize + py as usize * bw] = luma.data[x + y * luma.w];
+            }
+        }
+    }
+    Luma { w: bw, h: bh, data }
+}
+
+fn prepare(img: &RgbaImage, opts: &ImageOptions) -> Luma {
+    let mut luma = match fit_image(img, opts) {
+        Some(fitted) => Luma::from_rgba(&fitted),
+        None => Luma::from_rgba(img),
+    };
+    adjust(&mut luma, opts);
+    if opts.fit == Fit::Contain {
+        luma = letterbox(luma, opts);
+    }
+    luma
+}
+
+pub(crate) fn bitmap_from_image(img: &RgbaImage, opts: &ImageOptions) -> Bitmap {
+    dither::dither(&prepare(img, opts), opts.dither)
+}
+pub(crate) fn bitmap_from_dynimage(img: &DynamicImage, opts: &ImageOptions) -> Bitmap {
+    bitmap_from_image(&img.to_rgba8(), opts)
+}
+pub fn bitmap_from_memory(buf: &[u8], opts: &ImageOptions) -> anyhow::Result<Bitmap> {
+    let img = image::load_from_memory(buf)?;
+    Ok(bitmap_from_dynimage(&img, opts))
+}
+
+/// Like `bitmap_from_memory`, but quantizes to a `GrayBitmap` with `bits` per pixel. `opts.dither` is unused.
+pub fn gray_from_memory(buf: &[u8], bits: u8, opts: &ImageOptions) -> anyhow::Result<GrayBitmap> {
+    let img = image::load_from_memory(buf)?;
+    Ok(GrayBitmap::from_luma(&prepare(&img.to_rgba8(), opts), bits))
+}
+
+#[cfg(test)]
+#[test]
+fn fit_sizes() {
+    let img = RgbaImage::new(256, 256);
+    let opts = |fit| ImageOptions {
+        fit,
+        ..Default::default()
+    };
+    assert!(fit_image(&img, &opts(Fit::None)).is_none());
+    let anchored = ImageOptions {
+        anchor: Some(Anchor::TopLeft),
+        ..opts(Fit::None)
+    };
+    assert_eq!(fit_image(&img, &anchored).unwrap().dimensions(), (128, 64));
+    assert_eq!(fit_image(&img, &opts(Fit::Contain)).unwrap().dimensions(), (64, 64));
+    assert_eq!(fit_image(&img, &opts(Fit::Cover)).unwrap().dimensions(), (128, 64));
+    assert_eq!(fit_image(&img, &opts(Fit::Stretch)).unwrap().dimensions(), (128, 64));
+    assert!(fit_image(&RgbaImage::new(16, 16), &opts(Fit::None)).is_none());
+    // Inverting a contained image lights up the image, but not its padding
+    let inverted = ImageOptions {
+        invert: true,
+        ..opts(Fit::Contain)
+    };
+    let bitmap = bitmap_from_image(&RgbaImage::from_pixel(256, 256, image::Rgba([0, 0, 0, 255])), &inverted);
+    assert!(bitmap.get(64, 32) && !bitmap.get(0, 32));
+}
@@ -0,0 +1,333 @@
+// Conversion of grayscale images to 1-bit bitmaps.
+
+use ggoled_lib::{Bitmap, bitmap::BitVec};
+use std::sync::OnceLock;
+
+/// Method used when converting an image to 1-bit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    /// Hard threshold: pixels with a luminance of at least the given value are set.
+    Threshold(u8),
+    /// Hard threshold that is picked automatically per image using Otsu's method.
+    Otsu,
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    /// Ordered dithering with a Bayer matrix of the given size (2, 4 or 8).
+    Bayer(usize),
+    BlueNoise,
+}
+
+/// Grayscale image with luminance values in the range `0.0..=255.0`.
+pub(crate) struct Luma {
+    pub w: usize,
+    pub h: usize,
+    pub data: Vec<f32>,
+}
+impl Luma {
+    pub fn from_rgba(img: &image::RgbaImage) -> Self {
+        Self {
+            w: img.width() as usize,
+            h: img.height() as usize,
+            data: img
+                .pixels()
+                .map(|p| {
+                    let [r, g, b, a] = p.0.map(|c| c as f32);
+                    (r + g + b) * a / (3.0 * 255.0)
+                })
+                .collect(),
+        }
+    }
+}
+
+pub(crate) fn dither(luma: &Luma, mode: Dither) -> Bitmap {
+    match mode {
+        Dither::Threshold(t) => threshold(luma, t as f32),
+        Dither::Otsu => threshold(luma, otsu_threshold(luma) as f32 + 1.0),
+        Dither::FloydSteinberg => error_diffusion(luma, &FLOYD_STEINBERG, 16.0),
+        Dither::Atkinson => error_diffusion(luma, &ATKINSON, 8.0),
+        Dither::Sierra => error_diffusion(luma, &SIERRA, 32.0),
+        Dither::Bayer(size) => {
+            let size = size.clamp(2, 8).next_power_of_two();
+            ordered(luma, size, &bayer_matrix(size))
+        }
+        Dither::BlueNoise => ordered(luma, BLUE_NOISE_SIZE, blue_noise()),
+    }
+}
+
+fn threshold(luma: &Luma, t: f32) -> Bitmap {
+    Bitmap {
+        w: luma.w,
+        h: luma.h,
+        data: luma.data.iter().map(|&v| v >= t).collect::<BitVec>(),
+    }
+}
+
+// Returns the highest luminance of the "off" class
+fn otsu_threshold(luma: &Luma) -> u8 {
+    let mut histogram = [0usize; 256];
+    for &v in &luma.data {
+        histogram[v.round().clamp(0.0, 255.0) as usize] += 1;
+    }
+    let total = luma.data.len() as f64;
+    let sum_all: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+    let mut best = (0.0, 0);
+    let mut weight_bg = 0.0;
+    let mut sum_bg = 0.0;
+    for (i, &n) in histogram.iter().enumerate() {
+        weight_bg += n as f64;
+        if weight_bg == 0.0 {
+            continue;
+        }
+        let weight_fg = total - weight_bg;
+        if weight_fg == 0.0 {
+            break;
+        }
+        sum_bg += i as f64 * n as f64;
+        let mean_bg = sum_bg / weight_bg;
+        let mean_fg = (sum_all - sum_bg) / weight_fg;
+        let variance = weight_bg * weight_fg * (mean_bg - mean_fg).powi(2);
+        if variance > best.0 {
+            best = (variance, i);
+        }
+    }
+    best.1 as u8
+}
+
+// (dx, dy, weight)
+type Kernel = [(isize, usize, f32)];
+const FLOYD_STEINBERG: [(isize, usize, f32); 4] = [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)];
+const ATKINSON: [(isize, usize, f32); 6] = [
+    (1, 0, 1.0),
+    (2, 0, 1.0),
+    (-1, 1, 1.0),
+    (0, 1, 1.0),
+    (1, 1, 1.0),
+    (0, 2, 1.0),
+];
+const SIERRA: [(isize, usize, f32); 10] = [
+    (1, 0, 5.0),
+    (2, 0, 3.0),
+    (-2, 1, 2.0),
+    (-1, 1, 4.0),
+    (0, 1, 5.0),
+    (1, 1, 4.0),
+    (2, 1, 2.0),
+    (-1, 2, 2.0),
+    (0, 2, 3.0),
+    (1, 2, 2.0),
+];
+
+fn error_diffusion(luma: &Luma, kernel: &Kernel, divisor: f32) -> Bitmap {
+    let mut values = luma.data.clone();
+    let mut bitmap = Bitmap::new(luma.w, luma.h, false);
+    for y in 0..luma.h {
+        for x in 0..luma.w {
+            let i = x + y * luma.w;
+            let on = values[i] >= 128.0;
+            bitmap.data.set(i, on);
+            let error = values[i] - if on { 255.0 } else { 0.0 };
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx >= 0 && (nx as usize) < luma.w && ny < luma.h {
+                    values[nx as usize + ny * luma.w] += error * weight / divisor;
+                }
+            }
+        }
+    }
+    bitmap
+}
+
+// `matrix` contains ranks `0..size*size`
+fn ordered(luma: &Luma, size: usize, matrix: &[usize]) -> Bitmap {
+    let levels = (size * size) as f32;
+    let mut bitmap = Bitmap::new(luma.w, luma.h, false);
+    for y in 0..luma.h {
+        for x in 0..luma.w {
+            let rank = matrix[(x % size) + (y % size) * size];
+            let t = (rank as f32 + 0.5) / levels * 255.0;
+            bitmap.data.set(x + y * luma.w, luma.data[x + y * luma.w] > t);
+        }
+    }
+    bitmap
+}
+
+pub(crate) fn bayer_matrix(size: usize) -> Vec<usize> {
+    let mut matrix = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0; n * n * 4];
+        for y in 0..n {
+            for x in 0..n {
+                let v = matrix[x + y * n] * 4;
+                next[x + y * n * 2] = v;
+                next[(x + n) + y * n * 2] = v + 2;
+                next[x + (y + n) * n * 2] = v + 3;
+                next[(x + n) + (y + n) * n * 2] = v + 1;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+    matrix
+}
+
+/// Small deterministic PRNG (xorshift64*), good enough for noise patterns.
+pub(crate) struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+fn blue_noise() -> &'static [usize] {
+    static MATRIX: OnceLock<Vec<usize>> = OnceLock::new();
+    MATRIX.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE))
+}
+
+// Generates a tileable blue noise rank matrix using Ulichney's void-and-cluster method
+fn void_and_cluster(size: usize) -> Vec<usize> {
+    const SIGMA: f32 = 1.5;
+    let n = size * size;
+    let gauss: Vec<f32> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+    let splat = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * gauss[dx + dy * size];
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Initial pattern: random points, relaxed until evenly distributed
+    let mut rng = Rng::new(0x600d_b10e);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        let p = rng.below(n);
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+
+    // Rank the initial points by repeatedly removing the tightest cluster
+    let mut removing = pattern.clone();
+    let mut removing_energy = energy.clone();
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&removing, &removing_energy);
+        removing[cluster] = false;
+        splat(&mut removing_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // Rank the remaining points by repeatedly filling the largest void
+    for rank in initial..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+#[cfg(test)]
+fn gradient(w: usize, h: usize) -> Luma {
+    Luma {
+        w,
+        h,
+        data: (0..w * h).map(|i| (i % w) as f32 * 255.0 / (w - 1) as f32).collect(),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn bayer() {
+    assert_eq!(bayer_matrix(2), &[0, 2, 3, 1]);
+    let mut m8 = bayer_matrix(8);
+    m8.sort();
+    assert_eq!(m8, (0..64).collect::<Vec<_>>());
+}
+
+#[cfg(test)]
+#[test]
+fn blue_noise_is_permutation() {
+    let mut m = blue_noise().to_vec();
+    m.sort();
+    assert_eq!(m, (0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE).collect::<Vec<_>>());
+}
+
+#[cfg(test)]
+#[test]
+fn otsu_bimodal() {
+    let luma = Luma {
+        w: 4,
+        h: 1,
+        data: vec![10.0, 20.0, 200.0, 210.0],
+    };
+    let t = otsu_threshold(&luma);
+    assert!((20..200).contains(&t));
+    let bitmap = dither(&luma, Dither::Otsu);
+    assert_eq!(bitmap.data.iter().collect::<Vec<_>>(), &[false, false, true, true]);
+}
+
+#[cfg(test)]
+#[test]
+fn diffusion_preserves_average() {
+    let luma = gradient(64, 16);
+    for mode in [
+        Dither::FloydSteinberg,
+        Dither::Sierra,
+        Dither::Bayer(4),
+        Dither::BlueNoise,
+    ] {
+        let on = dither(&luma, mode).data.iter().filter(|b| *b).count() as f32;
+        let ratio = on / (64.0 * 16.0);
+        assert!((ratio - 0.5).abs() < 0.05, "{mode:?}: {ratio}");
+    }
+}
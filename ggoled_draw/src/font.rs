@@ -0,0 +1,393 @@
+// Bitmap fonts in the BDF and PCF formats, optionally gzipped, as used by X11 and most pixel fonts.
+
+use anyhow::{Context, bail};
+use ggoled_lib::Bitmap;
+use std::{collections::HashMap, io::Read};
+
+#[derive(Clone)]
+pub(crate) struct BitmapGlyph {
+    pub bitmap: Bitmap,
+    /// Offset of the bitmap's left edge from the pen position
+    pub x: i32,
+    /// Offset of the bitmap's bottom edge above the baseline
+    pub y: i32,
+    pub advance: i32,
+}
+
+pub(crate) struct BitmapFont {
+    pub ascent: i32,
+    pub descent: i32,
+    glyphs: HashMap<u32, BitmapGlyph>,
+    default_char: Option<u32>,
+}
+impl BitmapFont {
+    /// Whether `data` looks like a bitmap font rather than a TTF or OTF font.
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(b"STARTFONT") || data.starts_with(PCF_MAGIC) || data.starts_with(GZIP_MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if data.starts_with(GZIP_MAGIC) {
+            let mut unzipped = vec![];
+            flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut unzipped)
+                .context("Failed to decompress font")?;
+            if unzipped.starts_with(GZIP_MAGIC) {
+                bail!("Nested gzip in font");
+            }
+            return Self::parse(&unzipped);
+        }
+        if data.starts_with(PCF_MAGIC) {
+            parse_pcf(data)
+        } else if data.starts_with(b"STARTFONT") {
+            parse_bdf(std::str::from_utf8(data).context("BDF font is not valid text")?)
+        } else {
+            bail!("Unknown bitmap font format");
+        }
+    }
+
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.glyphs.contains_key(&(c as u32))
+    }
+
+    /// Glyph for `c`, or the font's default glyph if it has none.
+    pub fn glyph(&self, c: char) -> Option<&BitmapGlyph> {
+        self.glyphs
+            .get(&(c as u32))
+            .or_else(|| self.default_char.and_then(|d| self.glyphs.get(&d)))
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const PCF_MAGIC: &[u8] = b"\x01fcp";
+
+// Glyph that is being read from a BDF font
+struct BdfGlyph<'a> {
+    encoding: Option<u32>,
+    advance: i32,
+    bbx: [i32; 4],
+    rows: Vec<&'a str>,
+}
+
+fn parse_bdf(text: &str) -> anyhow::Result<BitmapFont> {
+    // Bounding box and offset that glyphs default to
+    let mut bounds = [0i32; 4];
+    let (mut ascent, mut descent, mut default_char) = (None, None, None);
+    let mut glyphs = HashMap::new();
+    let mut glyph: Option<BdfGlyph> = None;
+    let mut in_bitmap = false;
+    let ints = |args: &[&str]| -> anyhow::Result<Vec<i32>> {
+        args.iter()
+            .map(|a| {
+                a.parse::<i32>()
+                    .with_context(|| format!("Invalid number in BDF font: {a}"))
+            })
+            .collect()
+    };
+    for line in text.lines() {
+        let line = line.trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&key, args)) = words.split_first() else {
+            continue;
+        };
+        if in_bitmap && key != "ENDCHAR" {
+            if let Some(glyph) = &mut glyph {
+                glyph.rows.push(line);
+            }
+            continue;
+        }
+        match key {
+            "FONTBOUNDINGBOX" if args.len() >= 4 => bounds.copy_from_slice(&ints(&args[..4])?),
+            "FONT_ASCENT" if !args.is_empty() => ascent = Some(ints(&args[..1])?[0]),
+            "FONT_DESCENT" if !args.is_empty() => descent = Some(ints(&args[..1])?[0]),
+            "DEFAULT_CHAR" if !args.is_empty() => default_char = u32::try_from(ints(&args[..1])?[0]).ok(),
+            "STARTCHAR" => {
+                glyph = Some(BdfGlyph {
+                    encoding: None,
+                    advance: bounds[0],
+                    bbx: bounds,
+                    rows: vec![],
+                })
+            }
+            "ENCODING" if !args.is_empty() => {
+                if let Some(glyph) = &mut glyph {
+                    // Negative encodings are glyphs that aren't mapped to a character
+                    glyph.encoding = u32::try_from(ints(&args[..1])?[0]).ok();
+                }
+            }
+            "DWIDTH" if !args.is_empty() => {
+                if let Some(glyph) = &mut glyph {
+                    glyph.advance = ints(&args[..1])?[0];
+                }
+            }
+            "BBX" if args.len() >= 4 => {
+                if let Some(glyph) = &mut glyph {
+                    glyph.bbx.copy_from_slice(&ints(&args[..4])?);
+                }
+            }
+            "BITMAP" => in_bitmap = true,
+            "ENDCHAR" => {
+                in_bitmap = false;
+                let Some(BdfGlyph {
+                    encoding: Some(encoding),
+                    advance,
+                    bbx: [w, h, x, y],
+                    rows,
+                }) = glyph.take()
+                else {
+                    continue;
+                };
+                let (w, h) = (w.max(0) as usize, h.max(0) as usize);
+                let mut bitmap = Bitmap::new(w, h, false);
+                for (py, row) in rows.iter().take(h).enumerate() {
+                    for (i, byte) in row.as_bytes().chunks(2).enumerate() {
+                        let byte = std::str::from_utf8(byte)
+                            .ok()
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                            .context("Invalid bitmap in BDF font")?;
+                        for bit in 0..8 {
+                            if byte & (0x80 >> bit) != 0 {
+                                bitmap.set((i * 8 + bit) as isize, py as isize, true);
+                            }
+                        }
+                    }
+                }
+                glyphs.insert(encoding, BitmapGlyph { bitmap, x, y, advance });
+            }
+            _ => {}
+        }
+    }
+    if glyphs.is_empty() {
+        bail!("No glyphs in BDF font");
+    }
+    Ok(BitmapFont {
+        ascent: ascent.unwrap_or(bounds[1] + bounds[3]),
+        descent: descent.unwrap_or(-bounds[3]),
+        glyphs,
+        default_char,
+    })
+}
+
+const PCF_ACCELERATORS: u32 = 1 << 1;
+const PCF_METRICS: u32 = 1 << 2;
+const PCF_BITMAPS: u32 = 1 << 3;
+const PCF_BDF_ENCODINGS: u32 = 1 << 5;
+const PCF_BDF_ACCELERATORS: u32 = 1 << 8;
+
+// Table in a PCF font, which starts with a format that says how the rest is stored
+struct PcfTable<'a> {
+    format: u32,
+    data: &'a [u8],
+}
+impl PcfTable<'_> {
+    fn bytes<const N: usize>(&self, at: usize) -> anyhow::Result<[u8; N]> {
+        let bytes = self.data.get(at..at + N).context("PCF font is truncated")?;
+        let mut out: [u8; N] = bytes.try_into().unwrap();
+        if self.format & 4 == 0 {
+            out.reverse();
+        }
+        Ok(out)
+    }
+    fn u8(&self, at: usize) -> anyhow::Result<u8> {
+        Ok(self.bytes::<1>(at)?[0])
+    }
+    fn u16(&self, at: usize) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(at)?))
+    }
+    fn i16(&self, at: usize) -> anyhow::Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(at)?))
+    }
+    fn u32(&self, at: usize) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(at)?))
+    }
+    fn i32(&self, at: usize) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(at)?))
+    }
+}
+
+fn parse_pcf(data: &[u8]) -> anyhow::Result<BitmapFont> {
+    let le_u32 = |at: usize| -> anyhow::Result<u32> {
+        let bytes = data.get(at..at + 4).context("PCF font is truncated")?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let mut tables = HashMap::new();
+    for i in 0..le_u32(4)? as usize {
+        let entry = 8 + i * 16;
+        let (kind, size, offset) = (
+            le_u32(entry)?,
+            le_u32(entry + 8)? as usize,
+            le_u32(entry + 12)? as usize,
+        );
+        let table = data.get(offset..offset + size).context("PCF font is truncated")?;
+        if table.len() >= 4 {
+            let format = u32::from_le_bytes(table[..4].try_into().unwrap());
+            tables.insert(
+                kind,
+                PcfTable {
+                    format,
+                    data: &table[4..],
+                },
+            );
+        }
+    }
+    let table = |kind| {
+        tables
+            .get(&kind)
+            .with_context(|| format!("PCF font is missing table {kind}"))
+    };
+
+    // Per-glyph metrics: left and right bearing, advance, ascent and descent
+    let metrics = table(PCF_METRICS)?;
+    let mut glyph_metrics = vec![];
+    if metrics.format & 0x100 != 0 {
+        for i in 0..metrics.u16(0)? as usize {
+            let at = 2 + i * 5;
+            let m = |j: usize| Ok::<_, anyhow::Error>(metrics.u8(at + j)? as i32 - 0x80);
+            glyph_metrics.push([m(0)?, m(1)?, m(2)?, m(3)?, m(4)?]);
+        }
+    } else {
+        for i in 0..metrics.u32(0)? as usize {
+            let at = 4 + i * 12;
+            let m = |j: usize| Ok::<_, anyhow::Error>(metrics.i16(at + j * 2)? as i32);
+            glyph_metrics.push([m(0)?, m(1)?, m(2)?, m(3)?, m(4)?]);
+        }
+    }
+
+    let bitmaps = table(PCF_BITMAPS)?;
+    let count = bitmaps.u32(0)? as usize;
+    let data_start = 4 + count * 4 + 16;
+    let row_pad = 1 << (bitmaps.format & 3);
+    let msbit = bitmaps.format & 8 != 0;
+    let msbyte = bitmaps.format & 4 != 0;
+    let scan_unit = 1 << ((bitmaps.format >> 4) & 3);
+    let mut glyphs_by_index = vec![];
+    for (i, &[left, right, advance, ascent, descent]) in glyph_metrics.iter().enumerate().take(count) {
+        let (w, h) = ((right - left).max(0) as usize, (ascent + descent).max(0) as usize);
+        let row_bytes = w.div_ceil(8).div_ceil(row_pad) * row_pad;
+        let start = data_start + bitmaps.u32(4 + i * 4)? as usize;
+        let rows = bitmaps
+            .data
+            .get(start..start + row_bytes * h)
+            .context("PCF font is truncated")?;
+        let mut bitmap = Bitmap::new(w, h, false);
+        for (py, row) in rows.chunks(row_bytes).enumerate() {
+            let mut row = row.to_vec();
+            // Bytes are stored in the byte order within each scan unit, so swap them when the bit order differs
+            if msbit != msbyte && scan_unit > 1 {
+                for unit in row.chunks_mut(scan_unit) {
+                    unit.reverse();
+                }
+            }
+            for (px, on) in row
+                .iter()
+                .flat_map(|b| (0..8).map(move |bit| if msbit { b & (0x80 >> bit) } else { b & (1 << bit) } != 0))
+                .take(w)
+                .enumerate()
+            {
+                if on {
+                    bitmap.set(px as isize, py as isize, true);
+                }
+            }
+        }
+        glyphs_by_index.push(BitmapGlyph {
+            bitmap,
+            x: left,
+            y: -descent,
+            advance,
+        });
+    }
+
+    // Map characters to glyphs, where the character is made from two bytes. Several characters may share a glyph.
+    let encodings = table(PCF_BDF_ENCODINGS)?;
+    let (min_b2, max_b2) = (encodings.u16(0)? as u32, encodings.u16(2)? as u32);
+    let (min_b1, max_b1) = (encodings.u16(4)? as u32, encodings.u16(6)? as u32);
+    let default_char = encodings.u16(8)? as u32;
+    let mut glyphs = HashMap::new();
+    let per_row = max_b2.saturating_sub(min_b2) + 1;
+    for b1 in min_b1..=max_b1 {
+        for b2 in min_b2..=max_b2 {
+            let index = encodings.u16(10 + (((b1 - min_b1) * per_row + b2 - min_b2) * 2) as usize)?;
+            if let Some(glyph) = glyphs_by_index.get(index as usize) {
+                glyphs.insert(b1 << 8 | b2, glyph.clone());
+            }
+        }
+    }
+    if glyphs.is_empty() {
+        bail!("No glyphs in PCF font");
+    }
+
+    // The font's ascent and descent, after seven flags and a byte of padding
+    let accelerators = table(PCF_BDF_ACCELERATORS).or_else(|_| table(PCF_ACCELERATORS))?;
+    Ok(BitmapFont {
+        ascent: accelerators.i32(8)?,
+        descent: accelerators.i32(12)?,
+        glyphs,
+        default_char: Some(default_char),
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn bitmap_fonts() {
+    let check = |font: BitmapFont| {
+        assert_eq!((font.ascent, font.descent), (6, 2));
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!(
+            (glyph.bitmap.w, glyph.bitmap.h, glyph.x, glyph.y, glyph.advance),
+            (3, 2, 1, -1, 5)
+        );
+        assert!(glyph.bitmap.get(0, 0) && !glyph.bitmap.get(1, 0) && glyph.bitmap.get(2, 1));
+        // Missing characters use the default glyph
+        assert_eq!(font.glyph('?').unwrap().advance, 5);
+    };
+    let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 5 8 0 -2\nSTARTPROPERTIES 3\nFONT_ASCENT 6\nFONT_DESCENT 2\n\
+               DEFAULT_CHAR 65\nENDPROPERTIES\nCHARS 1\nSTARTCHAR A\nENCODING 65\nDWIDTH 5 0\nBBX 3 2 1 -1\n\
+               BITMAP\nA0\n20\nENDCHAR\nENDFONT\n";
+    check(BitmapFont::parse(bdf.as_bytes()).unwrap());
+
+    // The same font as PCF, with big endian tables, compressed metrics and rows padded to 4 bytes, and with `B` as an
+    // alias of `A`
+    let table = |format: u32, body: &[u8]| [&format.to_le_bytes()[..], body].concat();
+    let metrics = table(
+        0x10e,
+        &[&1u16.to_be_bytes()[..], &[0x81, 0x84, 0x85, 0x81, 0x81]].concat(),
+    );
+    let bitmaps = table(
+        0x0e,
+        &[
+            &1u32.to_be_bytes()[..],
+            &0u32.to_be_bytes(),
+            &[0; 16],
+            &[0xa0, 0, 0, 0, 0x20, 0, 0, 0],
+        ]
+        .concat(),
+    );
+    let encodings = table(
+        0x0e,
+        &[65u16, 66, 0, 0, 65, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>(),
+    );
+    let accelerators = table(0x0e, &[&[0; 8][..], &6i32.to_be_bytes(), &2i32.to_be_bytes()].concat());
+    let tables = [
+        (PCF_METRICS, metrics),
+        (PCF_BITMAPS, bitmaps),
+        (PCF_BDF_ENCODINGS, encodings),
+        (PCF_BDF_ACCELERATORS, accelerators),
+    ];
+    let mut pcf = [PCF_MAGIC, &(tables.len() as u32).to_le_bytes()].concat();
+    let mut offset = 8 + tables.len() * 16;
+    for (kind, data) in &tables {
+        for v in [*kind, 0x0e, data.len() as u32, offset as u32] {
+            pcf.extend(v.to_le_bytes());
+        }
+        offset += data.len();
+    }
+    for (_, data) in &tables {
+        pcf.extend(data);
+    }
+    let font = BitmapFont::parse(&pcf).unwrap();
+    assert!(font.has_glyph('A') && font.has_glyph('B'));
+    check(font);
+}
@@ -0,0 +1,195 @@
+// Decoding of (possibly animated) images into frames, either all at once or streamed from a background thread.
+
+use crate::convert::{ImageOptions, bitmap_from_dynimage, bitmap_from_image};
+use anyhow::Context;
+use ggoled_lib::Bitmap;
+use image::{
+    AnimationDecoder, DynamicImage, ImageFormat, ImageReader,
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+};
+use std::{
+    sync::{
+        Arc,
+        mpsc::{Receiver, TryRecvError, sync_channel},
+    },
+    task::Poll,
+    time::Duration,
+};
+
+#[derive(Clone)]
+pub struct Frame {
+    pub bitmap: Arc<Bitmap>,
+    pub delay: Option<Duration>,
+}
+
+type FrameIter = Box<dyn Iterator<Item = anyhow::Result<Frame>>>;
+
+fn animation_frames(decoder: impl AnimationDecoder<'static>, opts: &ImageOptions) -> FrameIter {
+    let opts = *opts;
+    Box::new(decoder.into_frames().map(move |frame| {
+        let frame = frame.context("Failed to decode animation frame")?;
+        let bitmap = Arc::new(bitmap_from_image(frame.buffer(), &opts));
+        Ok(Frame {
+            bitmap,
+            delay: Some({
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                Duration::from_millis(numer as u64 / denom.max(1) as u64)
+            }),
+        })
+    }))
+}
+
+fn still_frame(img: image::ImageResult<DynamicImage>, opts: &ImageOptions) -> FrameIter {
+    let frame = img.context("Failed to decode image").map(|img| Frame {
+        bitmap: Arc::new(bitmap_from_dynimage(&img, opts)),
+        delay: None,
+    });
+    Box::new(std::iter::once(frame))
+}
+
+/// Lazily decode the frames of an image. GIF, APNG and animated WebP files give one frame per animation frame with
+/// their embedded delays, with disposal and blending already applied. Anything else gives a single frame without delay.
+fn frame_iter(path: &str, opts: &ImageOptions) -> anyhow::Result<FrameIter> {
+    let reader = ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .with_context(|| format!("Failed to open image: {path}"))?;
+    Ok(match reader.format() {
+        Some(ImageFormat::Gif) => animation_frames(GifDecoder::new(reader.into_inner())?, opts),
+        Some(ImageFormat::Png) => {
+            let png = PngDecoder::new(reader.into_inner())?;
+            if png.is_apng()? {
+                animation_frames(png.apng()?, opts)
+            } else {
+                still_frame(DynamicImage::from_decoder(png), opts)
+            }
+        }
+        Some(ImageFormat::WebP) => {
+            let webp = WebPDecoder::new(reader.into_inner())?;
+            if webp.has_animation() {
+                animation_frames(webp, opts)
+            } else {
+                still_frame(DynamicImage::from_decoder(webp), opts)
+            }
+        }
+        _ => still_frame(reader.decode(), opts),
+    })
+}
+
+/// Decode all frames of an image up front. See `FrameStream` for long animations.
+pub fn decode_frames(path: &str, opts: &ImageOptions) -> anyhow::Result<Vec<Frame>> {
+    frame_iter(path, opts)?.collect()
+}
+
+/// Frames of one or more images, decoded in order on a background thread.
+/// At most `read_ahead` frames are decoded ahead of the consumer.
+pub struct FrameStream {
+    paths: Arc<[String]>,
+    opts: ImageOptions,
+    read_ahead: usize,
+    receiver: Receiver<anyhow::Result<Frame>>,
+    yielded: bool,
+}
+impl FrameStream {
+    pub fn open(paths: Vec<String>, opts: &ImageOptions, read_ahead: usize) -> Self {
+        let paths: Arc<[String]> = paths.into();
+        let receiver = Self::spawn(paths.clone(), *opts, read_ahead);
+        Self {
+            paths,
+            opts: *opts,
+            read_ahead,
+            receiver,
+            yielded: false,
+        }
+    }
+
+    fn spawn(paths: Arc<[String]>, opts: ImageOptions, read_ahead: usize) -> Receiver<anyhow::Result<Frame>> {
+        let (sender, receiver) = sync_channel(read_ahead.max(1));
+        std::thread::spawn(move || {
+            for path in paths.iter() {
+                let frames = match frame_iter(path, &opts) {
+                    Ok(frames) => frames,
+                    Err(err) => {
+                        _ = sender.send(Err(err));
+                        return;
+                    }
+                };
+                for frame in frames {
+                    let failed = frame.is_err();
+                    // Stop when the stream is dropped or restarted
+                    if sender.send(frame).is_err() || failed {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Start over from the first frame of the first image.
+    pub fn restart(&mut self) {
+        self.receiver = Self::spawn(self.paths.clone(), self.opts, self.read_ahead);
+        self.yielded = false;
+    }
+
+    /// Get the next frame without blocking. `Poll::Pending` means the frame hasn't been decoded yet.
+    pub fn try_next(&mut self) -> Poll<Option<anyhow::Result<Frame>>> {
+        match self.receiver.try_recv() {
+            Ok(frame) => {
+                self.yielded |= frame.is_ok();
+                Poll::Ready(Some(frame))
+            }
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+        }
+    }
+
+    /// Like `try_next`, but a decoding error also ends the stream.
+    pub(crate) fn try_next_frame(&mut self) -> Poll<Option<Frame>> {
+        self.try_next().map(|frame| frame.and_then(Result::ok))
+    }
+
+    /// Whether any frame has been decoded since the stream was (re)started.
+    pub(crate) fn yielded(&self) -> bool {
+        self.yielded
+    }
+}
+impl Iterator for FrameStream {
+    type Item = anyhow::Result<Frame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.receiver.recv().ok()?;
+        self.yielded |= frame.is_ok();
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn stream_restart() {
+    let dir = std::env::temp_dir().join(format!("ggoled_stream_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<String> = (1..=3u32)
+        .map(|w| {
+            let path = dir.join(format!("{w}.png"));
+            image::RgbaImage::new(w, 1).save(&path).unwrap();
+            path.to_string_lossy().into_owned()
+        })
+        .collect();
+    let mut stream = FrameStream::open(paths, &ImageOptions::default(), 1);
+    let widths = |stream: &mut FrameStream| stream.map(|f| f.unwrap().bitmap.w).collect::<Vec<_>>();
+    assert_eq!(widths(&mut stream), &[1, 2, 3]);
+    stream.restart();
+    assert_eq!(widths(&mut stream), &[1, 2, 3]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn decode_error() {
+    let path = std::env::temp_dir().join(format!("ggoled_bad_{}.gif", std::process::id()));
+    std::fs::write(&path, b"GIF89a\x10\x00").unwrap();
+    let path = path.to_string_lossy().into_owned();
+    assert!(decode_frames(&path, &ImageOptions::default()).is_err());
+    let mut stream = FrameStream::open(vec![path.clone()], &ImageOptions::default(), 1);
+    assert!(stream.next().unwrap().is_err() && stream.next().is_none());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,95 @@
+// Grayscale bitmaps that are shown on the 1-bit screen by flickering pixels over consecutive frames (PWM).
+
+use crate::dither::{Luma, bayer_matrix};
+use ggoled_lib::Bitmap;
+use std::sync::OnceLock;
+
+// 4x4 Bayer matrix, which is used for both quantizing and phase-shifting pixels
+fn bayer4() -> &'static [usize] {
+    static MATRIX: OnceLock<Vec<usize>> = OnceLock::new();
+    MATRIX.get_or_init(|| bayer_matrix(4))
+}
+
+/// Bitmap with `bits` (1-4) bits of gray per pixel. Each value in `data` is in the range `0..=max_level()`.
+#[derive(Debug, PartialEq)]
+pub struct GrayBitmap {
+    pub w: usize,
+    pub h: usize,
+    pub bits: u8,
+    pub data: Vec<u8>,
+}
+impl GrayBitmap {
+    /// Make a black bitmap. A pixel's PWM cycle is `max_level()` frames long, so at the device's usual 30 fps, 2 bits
+    /// cycle at 10 Hz while 4 bits cycle at 2 Hz and flicker visibly. Prefer 2 bits unless the shades matter more.
+    pub fn new(w: usize, h: usize, bits: u8) -> Self {
+        assert!((1..=4).contains(&bits), "gray bitmaps support 1-4 bits per pixel");
+        Self {
+            w,
+            h,
+            bits,
+            data: vec![0; w * h],
+        }
+    }
+
+    pub fn max_level(&self) -> u8 {
+        (1 << self.bits) - 1
+    }
+
+    /// Quantize an image to `bits` per pixel. Ordered dithering is used between adjacent levels to add perceived shades.
+    pub fn from_image(img: &image::RgbaImage, bits: u8) -> Self {
+        Self::from_luma(&Luma::from_rgba(img), bits)
+    }
+
+    pub(crate) fn from_luma(luma: &Luma, bits: u8) -> Self {
+        let mut gray = Self::new(luma.w, luma.h, bits);
+        let max = gray.max_level() as f32;
+        let matrix = bayer4();
+        for y in 0..luma.h {
+            for x in 0..luma.w {
+                let i = x + y * luma.w;
+                let bias = (matrix[(x % 4) + (y % 4) * 4] as f32 + 0.5) / 16.0;
+                gray.data[i] = (luma.data[i] / 255.0 * max + bias - 0.5).round().clamp(0.0, max) as u8;
+            }
+        }
+        gray
+    }
+
+    /// Get the 1-bit bitmap to show for a frame. Over `max_level()` consecutive frames, a pixel is set in as many
+    /// frames as its level. Pixels are phase-shifted in a pattern to avoid the whole image flickering in sync.
+    pub fn pwm_frame(&self, frame: usize) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        self.draw_frame(&mut bitmap, 0, 0, frame);
+        bitmap
+    }
+
+    /// Draw the pixels that are set for a frame onto `screen` at `x` and `y`, like blitting `pwm_frame` but without
+    /// allocating it.
+    pub fn draw_frame(&self, screen: &mut Bitmap, x: isize, y: isize, frame: usize) {
+        let max = self.max_level() as usize;
+        let matrix = bayer4();
+        for gy in 0..self.h {
+            for gx in 0..self.w {
+                let offset = matrix[(gx % 4) + (gy % 4) * 4] * max / 16;
+                if (frame + offset) % max < self.data[gx + gy * self.w] as usize {
+                    screen.set(x + gx as isize, y + gy as isize, true);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn pwm_duty_cycle() {
+    let mut gray = GrayBitmap::new(8, 8, 2);
+    for (i, v) in gray.data.iter_mut().enumerate() {
+        *v = (i % 4) as u8;
+    }
+    let mut counts = [0; 64];
+    for frame in 0..3 {
+        for (i, on) in gray.pwm_frame(frame).data.iter().enumerate() {
+            counts[i] += on as u8;
+        }
+    }
+    assert_eq!(counts.to_vec(), gray.data);
+}
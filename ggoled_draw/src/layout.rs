@@ -0,0 +1,439 @@
+// Flexbox-like layout of rows and columns, computing the boxes that layers are placed in.
+
+use crate::Rect;
+
+/// Size of a node along one axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    Fixed(usize),
+    /// A share of the space left over in the parent, by weight. Along the parent's cross axis, all of it.
+    Flex(usize),
+    /// The size of the content, including padding
+    Content,
+}
+
+/// Alignment of children along the cross axis of a row or column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Align {
+    Start,
+    #[default]
+    Center,
+    End,
+    /// Fill the cross axis, unless the child has a fixed size
+    Stretch,
+}
+
+/// Placement of children along the main axis of a row or column, when they don't fill it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Justify {
+    #[default]
+    Start,
+    Center,
+    End,
+    /// Spread the leftover space between the children
+    SpaceBetween,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Padding {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+impl Padding {
+    pub fn all(n: usize) -> Self {
+        Self {
+            left: n,
+            top: n,
+            right: n,
+            bottom: n,
+        }
+    }
+    pub fn xy(x: usize, y: usize) -> Self {
+        Self {
+            left: x,
+            top: y,
+            right: x,
+            bottom: y,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+    Row,
+    Column,
+}
+impl Axis {
+    // Swap between (x, y) and (main, cross)
+    fn flip<T>(self, (a, b): (T, T)) -> (T, T) {
+        match self {
+            Axis::Row => (a, b),
+            Axis::Column => (b, a),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum NodeKind<K> {
+    Item {
+        key: K,
+        content: (usize, usize),
+    },
+    Box {
+        axis: Axis,
+        gap: usize,
+        align: Align,
+        justify: Justify,
+        children: Vec<Node<K>>,
+    },
+}
+
+/// Node in a layout tree: either an item identified by a key, or a row or column of other nodes.
+#[derive(Clone, Debug)]
+pub struct Node<K> {
+    width: Size,
+    height: Size,
+    padding: Padding,
+    align: Option<Align>,
+    kind: NodeKind<K>,
+}
+impl<K: Clone + PartialEq> Node<K> {
+    /// An item sized to its content, e.g. the size of a bitmap or widget.
+    pub fn item(key: K, content: (usize, usize)) -> Self {
+        Self::new(Size::Content, NodeKind::Item { key, content })
+    }
+    /// Children placed left to right. Fills its parent by default.
+    pub fn row(children: Vec<Node<K>>) -> Self {
+        Self::container(Axis::Row, children)
+    }
+    /// Children placed top to bottom. Fills its parent by default.
+    pub fn column(children: Vec<Node<K>>) -> Self {
+        Self::container(Axis::Column, children)
+    }
+    fn container(axis: Axis, children: Vec<Node<K>>) -> Self {
+        Self::new(
+            Size::Flex(1),
+            NodeKind::Box {
+                axis,
+                gap: 0,
+                align: Align::default(),
+                justify: Justify::default(),
+                children,
+            },
+        )
+    }
+    fn new(size: Size, kind: NodeKind<K>) -> Self {
+        Self {
+            width: size,
+            height: size,
+            padding: Padding::default(),
+            align: None,
+            kind,
+        }
+    }
+
+    pub fn width(self, width: Size) -> Self {
+        Self { width, ..self }
+    }
+    pub fn height(self, height: Size) -> Self {
+        Self { height, ..self }
+    }
+    pub fn size(self, width: Size, height: Size) -> Self {
+        Self { width, height, ..self }
+    }
+    pub fn padding(self, padding: Padding) -> Self {
+        Self { padding, ..self }
+    }
+    /// Align this node within its parent, instead of using the parent's alignment.
+    pub fn align_self(self, align: Align) -> Self {
+        Self {
+            align: Some(align),
+            ..self
+        }
+    }
+    /// Space between the children of a row or column.
+    pub fn gap(mut self, n: usize) -> Self {
+        if let NodeKind::Box { gap, .. } = &mut self.kind {
+            *gap = n;
+        }
+        self
+    }
+    /// Cross axis alignment of the children of a row or column.
+    pub fn align(mut self, to: Align) -> Self {
+        if let NodeKind::Box { align, .. } = &mut self.kind {
+            *align = to;
+        }
+        self
+    }
+    /// Main axis placement of the children of a row or column.
+    pub fn justify(mut self, to: Justify) -> Self {
+        if let NodeKind::Box { justify, .. } = &mut self.kind {
+            *justify = to;
+        }
+        self
+    }
+
+    // Set the content size of the item with `key`, returning whether it changed
+    fn set_content(&mut self, key: &K, size: (usize, usize)) -> bool {
+        match &mut self.kind {
+            NodeKind::Item { key: k, content } if k == key => std::mem::replace(content, size) != size,
+            NodeKind::Item { .. } => false,
+            NodeKind::Box { children, .. } => children
+                .iter_mut()
+                .fold(false, |changed, child| child.set_content(key, size) | changed),
+        }
+    }
+
+    /// Smallest size that fits the content. Flex sizes are measured like content.
+    pub fn measure(&self) -> (usize, usize) {
+        let (pad_w, pad_h) = (
+            self.padding.left + self.padding.right,
+            self.padding.top + self.padding.bottom,
+        );
+        let content = match &self.kind {
+            NodeKind::Item { content, .. } => *content,
+            NodeKind::Box {
+                axis, gap, children, ..
+            } => {
+                let gaps = gap * children.len().saturating_sub(1);
+                let (w, h) = children
+                    .iter()
+                    .map(|c| c.measure())
+                    .fold((0, 0), |(w, h), (cw, ch)| match axis {
+                        Axis::Row => (w + cw, h.max(ch)),
+                        Axis::Column => (w.max(cw), h + ch),
+                    });
+                match axis {
+                    Axis::Row => (w + gaps, h),
+                    Axis::Column => (w, h + gaps),
+                }
+            }
+        };
+        let fixed = |size: Size, measured: usize| match size {
+            Size::Fixed(n) => n,
+            _ => measured,
+        };
+        (
+            fixed(self.width, content.0 + pad_w),
+            fixed(self.height, content.1 + pad_h),
+        )
+    }
+
+    /// Compute the box of every item when the node is placed in `rect`, in tree order.
+    pub fn compute(&self, rect: Rect) -> Vec<(K, Rect)> {
+        let mut out = vec![];
+        self.place(rect, &mut out);
+        out
+    }
+
+    fn place(&self, rect: Rect, out: &mut Vec<(K, Rect)>) {
+        let p = &self.padding;
+        let inner = Rect {
+            x: rect.x + p.left as isize,
+            y: rect.y + p.top as isize,
+            w: rect.w.saturating_sub(p.left + p.right),
+            h: rect.h.saturating_sub(p.top + p.bottom),
+        };
+        let NodeKind::Box {
+            axis,
+            gap,
+            align,
+            justify,
+            children,
+        } = &self.kind
+        else {
+            if let NodeKind::Item { key, .. } = &self.kind {
+                out.push((key.clone(), inner));
+            }
+            return;
+        };
+        // Work in (main, cross) coordinates
+        let flip = |pair| axis.flip(pair);
+        let (main, cross) = flip((inner.w, inner.h));
+        let main_size = |c: &Node<K>| axis.flip((c.width, c.height)).0;
+        let cross_size = |c: &Node<K>| axis.flip((c.width, c.height)).1;
+
+        let gaps = gap * children.len().saturating_sub(1);
+        let mut sizes: Vec<usize> = children
+            .iter()
+            .map(|c| match main_size(c) {
+                Size::Fixed(n) => n,
+                Size::Flex(_) => 0,
+                Size::Content => flip(c.measure()).0,
+            })
+            .collect();
+        let free = main.saturating_sub(sizes.iter().sum::<usize>() + gaps);
+        let total_weight: usize = children
+            .iter()
+            .filter_map(|c| match main_size(c) {
+                Size::Flex(weight) => Some(weight),
+                _ => None,
+            })
+            .sum();
+        // Share the free space by cumulative weight, so the shares always add up to it
+        let mut weight_so_far = 0;
+        for (size, child) in sizes.iter_mut().zip(children) {
+            if let Size::Flex(weight) = main_size(child)
+                && total_weight > 0
+            {
+                let before = free * weight_so_far / total_weight;
+                weight_so_far += weight;
+                *size = free * weight_so_far / total_weight - before;
+            }
+        }
+
+        let leftover = if total_weight > 0 { 0 } else { free } as isize;
+        let n = children.len() as isize;
+        let (mut pos, spread) = match justify {
+            Justify::Start => (0, 0),
+            Justify::Center => (leftover / 2, 0),
+            Justify::End => (leftover, 0),
+            Justify::SpaceBetween => (0, leftover),
+        };
+        for (i, (child, &size)) in children.iter().zip(&sizes).enumerate() {
+            let child_align = child.align.unwrap_or(*align);
+            let child_cross = match (cross_size(child), child_align) {
+                (Size::Fixed(n), _) => n,
+                (Size::Flex(_), _) | (_, Align::Stretch) => cross,
+                (Size::Content, _) => flip(child.measure()).1,
+            }
+            .min(cross);
+            let space = (cross - child_cross) as isize;
+            let cross_pos = match child_align {
+                Align::Start | Align::Stretch => 0,
+                Align::Center => space / 2,
+                Align::End => space,
+            };
+            let (x, y) = match axis {
+                Axis::Row => (pos, cross_pos),
+                Axis::Column => (cross_pos, pos),
+            };
+            let (w, h) = flip((size, child_cross));
+            child.place(
+                Rect {
+                    x: inner.x + x,
+                    y: inner.y + y,
+                    w,
+                    h,
+                },
+                out,
+            );
+            pos += size as isize + *gap as isize;
+            // Spread evenly, handing out any remainder to the later gaps
+            if n > 1 {
+                pos += spread * (i as isize + 1) / (n - 1) - spread * i as isize / (n - 1);
+            }
+        }
+    }
+}
+
+/// A layout tree placed in a box, which remembers where its items were placed so that only the items that moved or
+/// resized need to be updated when the content changes.
+#[derive(Clone, Debug)]
+pub struct Layout<K> {
+    root: Node<K>,
+    rect: Rect,
+    placed: Vec<(K, Rect)>,
+}
+impl<K: Clone + PartialEq> Layout<K> {
+    pub fn new(root: Node<K>, rect: Rect) -> Self {
+        Self {
+            root,
+            rect,
+            placed: vec![],
+        }
+    }
+    /// Change the content size of an item, e.g. when its text or bitmap changes.
+    pub fn set_content(&mut self, key: &K, size: (usize, usize)) -> bool {
+        self.root.set_content(key, size)
+    }
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+    /// Replace the layout tree, e.g. to show or hide items. Items keep their last box, so the next update only returns
+    /// the ones that moved or resized, or that are new.
+    pub fn set_root(&mut self, root: Node<K>) {
+        self.root = root;
+    }
+    /// Box of an item as of the last update.
+    pub fn get(&self, key: &K) -> Option<Rect> {
+        self.placed.iter().find(|(k, _)| k == key).map(|(_, rect)| *rect)
+    }
+    /// Run the layout again, returning the items whose box has changed since the last update.
+    pub fn update(&mut self) -> Vec<(K, Rect)> {
+        let placed = self.root.compute(self.rect);
+        let changed = placed
+            .iter()
+            .filter(|item| !self.placed.contains(item))
+            .cloned()
+            .collect();
+        self.placed = placed;
+        changed
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn layout_rows_and_columns() {
+    let screen = Rect {
+        x: 0,
+        y: 0,
+        w: 128,
+        h: 64,
+    };
+    let rect = |x, y, w, h| Rect { x, y, w, h };
+    let root = Node::column(vec![
+        Node::row(vec![
+            Node::item("icon", (16, 16)),
+            Node::item("title", (0, 0)).size(Size::Flex(1), Size::Flex(1)),
+            Node::item("battery", (20, 8)),
+        ])
+        .height(Size::Content)
+        .gap(4),
+        Node::item("body", (0, 0)).size(Size::Flex(1), Size::Flex(1)),
+        Node::row(vec![
+            Node::item("a", (10, 8)),
+            Node::item("b", (10, 8)),
+            Node::item("c", (10, 8)),
+        ])
+        .height(Size::Fixed(8))
+        .justify(Justify::SpaceBetween),
+    ])
+    .padding(Padding::all(2));
+    let mut layout = Layout::new(root, screen);
+    assert_eq!(
+        layout.update(),
+        vec![
+            ("icon", rect(2, 2, 16, 16)),
+            ("title", rect(22, 2, 80, 16)),
+            ("battery", rect(106, 6, 20, 8)),
+            ("body", rect(2, 18, 124, 36)),
+            ("a", rect(2, 54, 10, 8)),
+            ("b", rect(59, 54, 10, 8)),
+            ("c", rect(116, 54, 10, 8)),
+        ]
+    );
+    // Only the items affected by the bigger icon move
+    assert!(layout.set_content(&"icon", (20, 20)));
+    assert_eq!(
+        layout.update(),
+        vec![
+            ("icon", rect(2, 2, 20, 20)),
+            ("title", rect(26, 2, 76, 20)),
+            ("battery", rect(106, 8, 20, 8)),
+            ("body", rect(2, 22, 124, 32)),
+        ]
+    );
+    assert!(layout.update().is_empty());
+    // Dropping the top row moves the body, while the bottom row stays put
+    layout.set_root(
+        Node::column(vec![
+            Node::item("body", (0, 0)).size(Size::Flex(1), Size::Flex(1)),
+            Node::row(vec![Node::item("a", (10, 8))]).height(Size::Fixed(8)),
+        ])
+        .padding(Padding::all(2)),
+    );
+    assert_eq!(layout.update(), vec![("body", rect(2, 2, 124, 52))]);
+}
@@ -1,14 +1,27 @@
 // This is a wrapper around `ggoled_lib` that has high-level draw functions and additional events.
 // Heavily specialised for `ggoled_cli` and `ggoled_app`, and is therefore not recommended for general use.
 
-use anyhow::bail;
-use ggoled_lib::{Bitmap, Device, DeviceEvent, bitmap::BitVec};
-use image::{AnimationDecoder, ImageFormat, ImageReader, codecs::gif::GifDecoder};
-use parking_lot::{Mutex, MutexGuard};
-use rusttype::{Font, Scale, point};
+mod anim;
+mod clock;
+mod convert;
+mod dither;
+mod font;
+mod frames;
+mod gray;
+mod layout;
+mod markup;
+mod scroll;
+#[cfg(feature = "shaping")]
+mod shape;
+mod text;
+mod transition;
+mod tween;
+mod widgets;
+
+use ggoled_lib::{Bitmap, Device, DeviceEvent};
+use parking_lot::Mutex;
 use std::{
-    collections::BTreeMap,
-    path::PathBuf,
+    collections::{BTreeMap, HashMap},
     sync::{
         Arc,
         mpsc::{Receiver, Sender, channel},
@@ -16,123 +29,29 @@ use std::{
     time::{Duration, Instant},
 };
 
-pub struct TextRenderer {
-    font: Font<'static>,
-    size: f32,
-}
-impl TextRenderer {
-    pub fn load_from_file(path: &PathBuf, size: f32) -> anyhow::Result<Self> {
-        let data = std::fs::read(path)?;
-        let Some(font) = Font::try_from_vec(data) else {
-            bail!("Failed to load font");
-        };
-        Ok(Self { font, size })
-    }
-    pub fn new_pixel_operator() -> Self {
-        Self {
-            font: Font::try_from_bytes(include_bytes!("../fonts/PixelOperator.ttf")).unwrap(),
-            size: 16.0,
-        }
-    }
-    fn scale(&self) -> Scale {
-        Scale::uniform(self.size)
-    }
-    pub fn line_height(&self) -> usize {
-        let v_metrics = self.font.v_metrics(self.scale());
-        (v_metrics.ascent - v_metrics.descent).ceil() as usize
-    }
-    pub fn render_lines(&self, text: &str) -> Vec<Bitmap> {
-        let clean_text = text.replace('\r', "");
-        let text_lines = clean_text.split('\n');
-        text_lines
-            .map(|text_line| {
-                let glyphs: Vec<_> = self.font.layout(text_line, self.scale(), point(0.0, 0.0)).collect();
-                let mut line_w_offset = 0;
-                let mut line_h_offset = 0;
-                let mut line_w = 0;
-                let mut line_h = 0;
-                for bb in glyphs.iter().filter_map(|g| g.pixel_bounding_box()) {
-                    line_w_offset = line_w_offset.max(-bb.min.x);
-                    line_h_offset = line_h_offset.max(-bb.min.y);
-                    line_w = line_w.max(bb.max.x + 1);
-                    line_h = line_h.max(bb.max.y + 1);
-                }
-                let line_w = (line_w + line_w_offset) as usize;
-                let line_h = (line_h + line_h_offset) as usize;
-                let mut bitmap = Bitmap::new(line_w, line_h, false);
-                for glyph in glyphs {
-                    if let Some(bb) = glyph.pixel_bounding_box() {
-                        glyph.draw(|x, y, v| {
-                            if v > 0.5 {
-                                let px = (x as i32 + line_w_offset + bb.min.x) as usize;
-                                let py = (y as i32 + line_h_offset + bb.min.y) as usize;
-                                bitmap.data.set(py * line_w + px, true);
-                            }
-                        })
-                    }
-                }
-                bitmap
-            })
-            .collect()
-    }
-}
+pub use anim::{AnimParams, LagPolicy, Playback};
+pub use clock::ClockStyle;
+pub use convert::{Anchor, FilterType, Fit, ImageOptions, bitmap_from_memory, gray_from_memory};
+pub use dither::Dither;
+pub use frames::{Frame, FrameStream, decode_frames};
+pub use gray::GrayBitmap;
+pub use layout::{Align, Justify, Layout, Node, Padding, Size};
+pub use markup::escape_markup;
+pub use scroll::{ScrollDirection, ScrollMode, ScrollParams};
+pub use text::{Halo, TextAlign, TextOptions, TextRenderer, TextStyle, Wrap};
+pub use transition::{Direction, Easing, Transition, TransitionKind};
+pub use tween::{Keyframe, Repeat, Timeline};
+pub use widgets::{Battery, Fill, Gauge, Meter, ProgressBar, Sparkline, Widget, WidgetStyle};
 
-fn bitmap_from_image(img: &image::RgbaImage, threshold: u8) -> Bitmap {
-    let t3 = (threshold as usize) * 3 * 255;
-    Bitmap {
-        w: img.width() as usize,
-        h: img.height() as usize,
-        data: img
-            .pixels()
-            .map(|p| {
-                let r = p.0[0] as usize * p.0[3] as usize;
-                let g = p.0[1] as usize * p.0[3] as usize;
-                let b = p.0[2] as usize * p.0[3] as usize;
-                (r + g + b) >= t3
-            })
-            .collect::<BitVec>(),
-    }
-}
-fn bitmap_from_dynimage(img: &image::DynamicImage, threshold: u8) -> Bitmap {
-    bitmap_from_image(&img.to_rgba8(), threshold)
-}
-pub fn bitmap_from_memory(buf: &[u8], threshold: u8) -> anyhow::Result<Bitmap> {
-    let img = image::load_from_memory(buf)?;
-    Ok(bitmap_from_dynimage(&img, threshold))
-}
-
-#[derive(Clone)]
-pub struct Frame {
-    pub bitmap: Arc<Bitmap>,
-    pub delay: Option<Duration>,
-}
-
-pub fn decode_frames(path: &str, threshold: u8) -> Vec<Frame> {
-    let reader = ImageReader::open(path).expect("Failed to open image");
-    if matches!(reader.format().unwrap(), ImageFormat::Gif) {
-        let gif = GifDecoder::new(reader.into_inner()).expect("Failed to decode gif");
-        let frames = gif.into_frames();
-        frames
-            .map(|frame| {
-                let frame = frame.expect("Failed to decode gif frame");
-                let bitmap = Arc::new(bitmap_from_image(frame.buffer(), threshold));
-                Frame {
-                    bitmap,
-                    delay: Some({
-                        let (numer, denom) = frame.delay().numer_denom_ms();
-                        Duration::from_millis(numer as u64 / denom.max(1) as u64)
-                    }),
-                }
-            })
-            .collect()
-    } else {
-        let img = reader.decode().expect("Failed to decode image");
-        let bitmap = Arc::new(bitmap_from_dynimage(&img, threshold));
-        vec![Frame { bitmap, delay: None }]
-    }
-}
+use anim::AnimState;
+use chrono::Local;
+use clock::ClockState;
+use scroll::ScrollState;
+use text::TextCache;
+use transition::ActiveTransition;
+use tween::TimelineState;
 
-#[derive(Clone, Copy, PartialEq, PartialOrd, Hash, Eq, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Hash, Eq, Ord)]
 pub struct LayerId(usize);
 impl LayerId {
     pub fn none() -> LayerId {
@@ -140,6 +59,31 @@ impl LayerId {
     }
 }
 
+/// Rectangle in screen coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: isize,
+    pub y: isize,
+    pub w: usize,
+    pub h: usize,
+}
+
+/// Frames of an animation layer. Streams loop by restarting once they end.
+pub enum FrameSource {
+    Frames(Vec<Frame>),
+    Stream(FrameStream),
+}
+impl From<Vec<Frame>> for FrameSource {
+    fn from(frames: Vec<Frame>) -> Self {
+        FrameSource::Frames(frames)
+    }
+}
+impl From<FrameStream> for FrameSource {
+    fn from(stream: FrameStream) -> Self {
+        FrameSource::Stream(stream)
+    }
+}
+
 pub enum DrawLayer {
     Image {
         bitmap: Arc<Bitmap>,
@@ -147,17 +91,150 @@ pub enum DrawLayer {
         y: isize,
     },
     Animation {
-        frames: Vec<Frame>,
+        frames: FrameSource,
         x: isize,
         y: isize,
         follow_fps: bool,
+        params: AnimParams,
     },
     Scroll {
         bitmap: Arc<Bitmap>,
+        x: isize,
+        y: isize,
+        params: ScrollParams,
+    },
+    /// Grayscale image, shown by time-multiplexing pixels over consecutive frames
+    Gray {
+        bitmap: Arc<GrayBitmap>,
+        x: isize,
+        y: isize,
+    },
+    /// Layers positioned relative to `x` and `y`, drawn in order. `clip` is also relative, and hides anything drawn
+    /// outside of it.
+    Group {
+        layers: Vec<DrawLayer>,
+        x: isize,
         y: isize,
+        clip: Option<Rect>,
+    },
+    /// Text in a box, which is only rasterized again when the text or font changes. Lines are centered vertically in
+    /// the box, and lines that are wider than it scroll.
+    Text {
+        text: String,
+        font: Arc<TextRenderer>,
+        align: TextAlign,
+        rect: Rect,
+        scroll: ScrollParams,
+    },
+    /// The current time in a box, updated by the render thread right as it changes
+    Clock {
+        /// strftime format of digital clocks, e.g. `%H:%M:%S`
+        format: String,
+        font: Arc<TextRenderer>,
+        style: ClockStyle,
+        rect: Rect,
     },
 }
 
+impl DrawLayer {
+    fn kind(&self) -> LayerKind {
+        match self {
+            DrawLayer::Image { .. } => LayerKind::Image,
+            DrawLayer::Animation { .. } => LayerKind::Animation,
+            DrawLayer::Scroll { .. } => LayerKind::Scroll,
+            DrawLayer::Gray { .. } => LayerKind::Gray,
+            DrawLayer::Group { .. } => LayerKind::Group,
+            DrawLayer::Text { .. } => LayerKind::Text,
+            DrawLayer::Clock { .. } => LayerKind::Clock,
+        }
+    }
+    fn position(&self) -> (isize, isize) {
+        match self {
+            DrawLayer::Scroll {
+                params: ScrollParams { clip: Some(clip), .. },
+                ..
+            } => (clip.x, clip.y),
+            DrawLayer::Image { x, y, .. }
+            | DrawLayer::Animation { x, y, .. }
+            | DrawLayer::Scroll { x, y, .. }
+            | DrawLayer::Gray { x, y, .. }
+            | DrawLayer::Group { x, y, .. } => (*x, *y),
+            DrawLayer::Text { rect, .. } | DrawLayer::Clock { rect, .. } => (rect.x, rect.y),
+        }
+    }
+    // Scroll layers with a clip box move the box instead
+    fn set_position(&mut self, new_x: isize, new_y: isize) {
+        match self {
+            DrawLayer::Scroll {
+                params: ScrollParams { clip: Some(clip), .. },
+                ..
+            } => (clip.x, clip.y) = (new_x, new_y),
+            DrawLayer::Image { x, y, .. }
+            | DrawLayer::Animation { x, y, .. }
+            | DrawLayer::Scroll { x, y, .. }
+            | DrawLayer::Gray { x, y, .. }
+            | DrawLayer::Group { x, y, .. } => (*x, *y) = (new_x, new_y),
+            DrawLayer::Text { rect, .. } | DrawLayer::Clock { rect, .. } => (rect.x, rect.y) = (new_x, new_y),
+        }
+    }
+    // Text, clocks and scroll layers fill the box, group clip boxes are resized, and other layers are only moved
+    fn set_rect(&mut self, new: Rect) {
+        match self {
+            DrawLayer::Text { rect, .. } | DrawLayer::Clock { rect, .. } => *rect = new,
+            DrawLayer::Scroll { params, .. } => params.clip = Some(new),
+            DrawLayer::Group { clip: Some(clip), .. } => {
+                (clip.w, clip.h) = (new.w, new.h);
+                self.set_position(new.x, new.y);
+            }
+            _ => self.set_position(new.x, new.y),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerKind {
+    Image,
+    Animation,
+    Scroll,
+    Gray,
+    Group,
+    Text,
+    Clock,
+}
+
+/// Snapshot of a layer's properties, as currently seen by the render thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerInfo {
+    pub kind: LayerKind,
+    pub x: isize,
+    pub y: isize,
+    /// Size of the current bitmap or frame. For groups, the size of the clip box or the extent of the children, and
+    /// for text and clocks the size of their box.
+    pub w: usize,
+    pub h: usize,
+    pub visible: bool,
+    pub z: i32,
+}
+
+/// Options for adding layers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LayerOptions {
+    /// Stacking order, where layers with a higher `z` are drawn on top. Layers with the same `z` are stacked in the
+    /// order they were added.
+    pub z: i32,
+    /// When the render thread removes the layer by itself, sending `DrawEvent::LayerExpired`
+    pub expiry: Option<Instant>,
+}
+impl LayerOptions {
+    /// Expire the layer after `lifetime` from now.
+    pub fn with_lifetime(self, lifetime: Duration) -> Self {
+        Self {
+            expiry: Some(Instant::now() + lifetime),
+            ..self
+        }
+    }
+}
+
 pub enum ShiftMode {
     Off,
     Low,
@@ -177,20 +254,215 @@ pub enum DrawEvent {
     DeviceDisconnected,
     DeviceReconnected,
     DeviceEvent(DeviceEvent),
-}
-
-struct AnimState {
-    ticks: usize,
-    next_update: Instant,
-}
-struct ScrollState {
-    x: isize,
+    LayerExpired(LayerId),
+    /// An animation layer played all of its loops and stopped on its last frame. For animations in a group, this is
+    /// sent with the id of the group.
+    AnimationFinished(LayerId),
 }
 
 struct DrawLayerState {
     layer: DrawLayer,
+    visible: bool,
+    z: i32,
+    expiry: Option<Instant>,
     anim: AnimState,
     scroll: ScrollState,
+    timeline: Option<TimelineState>,
+    // States of the layers in a group, which are moved out of `layer`
+    children: Vec<DrawLayerState>,
+    text: Option<TextCache>,
+    clock: ClockState,
+}
+impl DrawLayerState {
+    fn new(layer: DrawLayer, opts: LayerOptions) -> Self {
+        let mut state = Self {
+            layer,
+            visible: true,
+            z: opts.z,
+            expiry: opts.expiry,
+            anim: AnimState::new(),
+            scroll: ScrollState::new(),
+            timeline: None,
+            children: vec![],
+            text: None,
+            clock: ClockState::new(),
+        };
+        state.take_children();
+        state
+    }
+    fn take_children(&mut self) {
+        if let DrawLayer::Group { layers, .. } = &mut self.layer {
+            self.children = std::mem::take(layers)
+                .into_iter()
+                .map(|layer| DrawLayerState::new(layer, LayerOptions::default()))
+                .collect();
+        }
+    }
+    // Apply a change, or ignore it if it doesn't apply to the kind of layer
+    fn apply(&mut self, change: LayerChange) {
+        if !change.applies_to(self.layer.kind()) {
+            return;
+        }
+        match (change, &mut self.layer) {
+            (LayerChange::Replace(layer), _) => {
+                self.layer = layer;
+                self.take_children();
+            }
+            (LayerChange::Position(x, y), layer) => layer.set_position(x, y),
+            (LayerChange::Rect(rect), layer) => layer.set_rect(rect),
+            (LayerChange::Bitmap(new_bitmap), DrawLayer::Image { bitmap, .. } | DrawLayer::Scroll { bitmap, .. }) => {
+                *bitmap = new_bitmap
+            }
+            (LayerChange::Frames(new_frames), DrawLayer::Animation { frames, .. }) => *frames = new_frames,
+            (LayerChange::Visible(visible), _) => self.visible = visible,
+            (LayerChange::Text(new_text), DrawLayer::Text { text, .. }) => *text = new_text,
+            (LayerChange::Timeline(timeline), _) => {
+                self.timeline = timeline.map(|timeline| TimelineState { timeline, start: None })
+            }
+            (LayerChange::Paused(paused), DrawLayer::Animation { .. }) => self.anim.set_paused(paused),
+            (LayerChange::Seek(frame), DrawLayer::Animation { frames, params, .. }) => {
+                self.anim.seek(frames, params, frame)
+            }
+            _ => {}
+        }
+    }
+    /// Update and blit the layer to `screen`, moved by `offset`. Returns true when an animation in the layer just
+    /// finished playing.
+    fn render(&mut self, screen: &mut Bitmap, offset: (isize, isize), time: Instant, pwm_frame: usize) -> bool {
+        let (ox, oy) = offset;
+        match &mut self.layer {
+            DrawLayer::Image { bitmap, x, y } => screen.blit(bitmap, *x + ox, *y + oy, false),
+            DrawLayer::Animation {
+                frames,
+                x,
+                y,
+                follow_fps,
+                params,
+            } => {
+                let finished = self.anim.update(frames, params, *follow_fps, time);
+                if let Some(frame) = &self.anim.current {
+                    screen.blit(&frame.bitmap, *x + ox, *y + oy, false);
+                }
+                return finished;
+            }
+            DrawLayer::Scroll { bitmap, x, y, params } => {
+                self.scroll
+                    .render(screen, (bitmap, None), (*x, *y), offset, params, time)
+            }
+            DrawLayer::Gray { bitmap, x, y } => bitmap.draw_frame(screen, *x + ox, *y + oy, pwm_frame),
+            DrawLayer::Group { x, y, clip, .. } => {
+                let origin = (*x + ox, *y + oy);
+                let mut finished = false;
+                if let Some(clip) = *clip {
+                    let mut boxed = Bitmap::new(clip.w, clip.h, false);
+                    for child in &mut self.children {
+                        finished |= child.render(&mut boxed, (-clip.x, -clip.y), time, pwm_frame);
+                    }
+                    screen.blit(&boxed, origin.0 + clip.x, origin.1 + clip.y, false);
+                } else {
+                    for child in &mut self.children {
+                        finished |= child.render(screen, origin, time, pwm_frame);
+                    }
+                }
+                return finished;
+            }
+            DrawLayer::Text {
+                text,
+                font,
+                align,
+                rect,
+                scroll,
+            } => TextCache::update(&mut self.text, text, font).render(screen, *align, *rect, scroll, offset, time),
+            DrawLayer::Clock {
+                format,
+                font,
+                style,
+                rect,
+            } => {
+                self.clock.update(format, *style, (rect.w, rect.h), Local::now());
+                match style {
+                    ClockStyle::Digital => TextCache::update(&mut self.text, &self.clock.text, font).render(
+                        screen,
+                        TextAlign::Center,
+                        *rect,
+                        &ScrollParams::default(),
+                        offset,
+                        time,
+                    ),
+                    ClockStyle::Analog { .. } => {
+                        if let Some(face) = &self.clock.face {
+                            screen.blit(face, rect.x + ox, rect.y + oy, false);
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+    // Time until a clock in the layer changes
+    fn until_clock_tick(&self, now: chrono::DateTime<Local>) -> Option<Duration> {
+        match &self.layer {
+            DrawLayer::Clock { format, style, .. } => Some(clock::until_next_tick(format, *style, now)),
+            DrawLayer::Group { .. } => self
+                .children
+                .iter()
+                .filter_map(|child| child.until_clock_tick(now))
+                .min(),
+            _ => None,
+        }
+    }
+    fn update_timeline(&mut self, time: Instant) {
+        let Some(state) = &mut self.timeline else {
+            return;
+        };
+        let start = *state.start.get_or_insert(time);
+        let (x, y, visible, done) = state.timeline.sample(time.duration_since(start));
+        if x.is_some() || y.is_some() {
+            let (cur_x, cur_y) = self.layer.position();
+            self.layer.set_position(x.unwrap_or(cur_x), y.unwrap_or(cur_y));
+        }
+        if let Some(visible) = visible {
+            self.visible = visible;
+        }
+        if done {
+            self.timeline = None;
+        }
+    }
+    fn info(&self) -> LayerInfo {
+        let (x, y) = self.layer.position();
+        let (w, h) = match &self.layer {
+            DrawLayer::Image { bitmap, .. } | DrawLayer::Scroll { bitmap, .. } => (bitmap.w, bitmap.h),
+            DrawLayer::Gray { bitmap, .. } => (bitmap.w, bitmap.h),
+            DrawLayer::Group { clip: Some(clip), .. }
+            | DrawLayer::Text { rect: clip, .. }
+            | DrawLayer::Clock { rect: clip, .. } => (clip.w, clip.h),
+            DrawLayer::Group { clip: None, .. } => {
+                self.children
+                    .iter()
+                    .map(|child| child.info())
+                    .fold((0, 0), |(w, h), info| {
+                        (
+                            w.max((info.x + info.w as isize).max(0) as usize),
+                            h.max((info.y + info.h as isize).max(0) as usize),
+                        )
+                    })
+            }
+            DrawLayer::Animation { frames, params, .. } => self
+                .anim
+                .peek(frames, params)
+                .map(|f| (f.bitmap.w, f.bitmap.h))
+                .unwrap_or_default(),
+        };
+        LayerInfo {
+            kind: self.layer.kind(),
+            x,
+            y,
+            w,
+            h,
+            visible: self.visible,
+            z: self.z,
+        }
+    }
 }
 
 const OLED_SHIFT_PERIOD: Duration = Duration::from_secs(90);
@@ -227,7 +499,7 @@ const RECONNECT_PERIOD: Duration = Duration::from_secs(1);
 
 fn run_draw_device_thread(
     mut dev: Device,
-    layers: Arc<Mutex<LayerMap>>,
+    scene: Arc<Mutex<Scene>>,
     cmd_receiver: Receiver<DrawCommand>,
     event_sender: Sender<DrawEvent>,
     fps: usize,
@@ -244,6 +516,11 @@ fn run_draw_device_thread(
     let mut connected = true;
     let mut last_connect_attempt = Instant::now();
     let mut last_frame_time = Instant::now();
+    let mut pwm_frame: usize = 0;
+    let mut transition: Option<ActiveTransition> = None;
+    let mut next_transition: Option<Transition> = None;
+    let mut next_clock_tick: Option<Instant> = None;
+    let mut layers = LayerMap::new();
     loop {
         let time = Instant::now();
         let mut stop_after_frame = false;
@@ -266,8 +543,33 @@ fn run_draw_device_thread(
             }
         }
 
+        // Pick up committed transactions. The lock is only held to take their changes, and if a transaction is being
+        // committed right now its changes are picked up next frame instead of waiting on it.
+        if let Some(mut scene) = scene.try_lock() {
+            let ops = std::mem::take(&mut scene.ops);
+            if let Some(committed) = scene.transition.take() {
+                next_transition = Some(committed);
+            }
+            drop(scene);
+            apply_ops(&mut layers, ops);
+        }
+
+        // Remove expired layers
+        layers.retain(|id, state| {
+            let expired = state.expiry.is_some_and(|expiry| time >= expiry);
+            if expired {
+                event_sender.send(DrawEvent::LayerExpired(*id)).unwrap();
+            }
+            !expired
+        });
+
         // Render frame
         if connected && playing {
+            // Transitions start from what was last drawn
+            if let Some(next) = next_transition.take() {
+                transition = Some(ActiveTransition::new(next, prev_screen.clone(), time));
+            }
+
             // Handle OLED shifts
             let (shift_x, shift_y) = match shift_mode {
                 ShiftMode::Off => (0, 0),
@@ -289,51 +591,32 @@ fn run_draw_device_thread(
 
             // Update and blit each layer to the screen
             let mut screen = Bitmap::new(dev.width, dev.height, false);
-            let mut layers = layers.lock();
-            for (_, state) in layers.iter_mut() {
-                match &state.layer {
-                    DrawLayer::Image { bitmap, x, y } => screen.blit(bitmap, x + shift_x, y + shift_y, false),
-                    DrawLayer::Animation {
-                        frames,
-                        x,
-                        y,
-                        follow_fps,
-                    } => {
-                        if !frames.is_empty() {
-                            let frame = &frames[state.anim.ticks % frames.len()];
-                            screen.blit(&frame.bitmap, x + shift_x, y + shift_y, false);
-                            if *follow_fps {
-                                state.anim.ticks += 1;
-                            } else if time >= state.anim.next_update {
-                                state.anim.ticks += 1;
-                                // TODO: handle falling behind
-                                if let Some(delay) = frame.delay {
-                                    state.anim.next_update += delay.max(Duration::from_millis(10));
-                                }
-                            }
-                        }
-                    }
-                    DrawLayer::Scroll { bitmap, y } => {
-                        const MARGIN: isize = 30;
-                        let scroll_w = bitmap.w as isize + MARGIN;
-                        let dupes = 1 + dev.width / scroll_w as usize;
-                        for i in 0..=dupes {
-                            screen.blit(
-                                bitmap,
-                                state.scroll.x + i as isize * scroll_w + shift_x,
-                                *y + shift_y,
-                                false,
-                            );
-                        }
-                        state.scroll.x -= 1;
-                        if state.scroll.x <= -scroll_w {
-                            state.scroll.x += scroll_w;
-                        }
-                    }
+            for state in layers.values_mut() {
+                state.update_timeline(time);
+            }
+            let mut stack: Vec<_> = layers.iter_mut().filter(|(_, state)| state.visible).collect();
+            stack.sort_by_key(|(id, state)| (state.z, **id));
+            for (id, state) in stack {
+                if state.render(&mut screen, (shift_x, shift_y), time, pwm_frame) {
+                    event_sender.send(DrawEvent::AnimationFinished(*id)).unwrap();
                 }
             }
+            let now = Local::now();
+            next_clock_tick = layers
+                .values()
+                .filter(|state| state.visible)
+                .filter_map(|state| state.until_clock_tick(now))
+                .min()
+                .map(|until| Instant::now() + until);
 
-            drop(layers);
+            if let Some(active) = &transition {
+                if active.done(time) {
+                    transition = None;
+                } else {
+                    screen = active.mix(&screen, time);
+                }
+            }
+            pwm_frame = pwm_frame.wrapping_add(1);
 
             // Draw update
             let frame_time = Instant::now();
@@ -351,6 +634,12 @@ fn run_draw_device_thread(
             }
         }
 
+        // Publish the layer properties for `DrawDevice::layer_info`
+        let infos = layers.iter().map(|(id, state)| (*id, state.info())).collect();
+        if let Some(mut scene) = scene.try_lock() {
+            scene.infos = infos;
+        }
+
         // Get device events and pass back to DrawDevice
         if connected {
             let events = dev.get_events().unwrap_or_else(|_| {
@@ -371,41 +660,67 @@ fn run_draw_device_thread(
         // Delay as long as needed based on how long frame rendering took (which will mostly depend on USB speed)
         let frame_duration = Instant::now().duration_since(time);
         // println!("frame: {:?}, {:?}", frame_duration, frame_delay);
-        spin_sleep::sleep(frame_delay.saturating_sub(frame_duration));
+        let mut delay = frame_delay.saturating_sub(frame_duration);
+        // Render early if a clock is about to change, so that it's shown on time. The deadline is only used once, since
+        // it's not updated while frames aren't rendered, such as when disconnected or paused.
+        if let Some(tick) = next_clock_tick.take() {
+            delay = delay.min(tick.saturating_duration_since(Instant::now()));
+        }
+        spin_sleep::sleep(delay);
     }
     dev
 }
 
 type LayerMap = BTreeMap<LayerId, DrawLayerState>;
+// State shared between `DrawDevice` and the render thread, which owns the layers themselves
+#[derive(Default)]
+struct Scene {
+    // Changes of committed transactions, in order, that the render thread hasn't applied yet
+    ops: Vec<LayerOp>,
+    // Started by the render thread in the first frame that shows the layers it was committed with
+    transition: Option<Transition>,
+    // Layer properties as of the last frame
+    infos: BTreeMap<LayerId, LayerInfo>,
+}
 pub struct DrawDevice {
     width: usize,
     height: usize,
-    layers: Arc<Mutex<LayerMap>>,
+    scene: Arc<Mutex<Scene>>,
     layer_counter: usize,
     thread: Option<std::thread::JoinHandle<Device>>,
     cmd_sender: Sender<DrawCommand>,
     event_receiver: Receiver<DrawEvent>,
-    pub texter: TextRenderer,
+    pub texter: Arc<TextRenderer>,
+    /// Used for text lines that are too wide for the screen
+    pub scroll: ScrollParams,
+    /// Used by `add_text`. Text that isn't wrapped or limited to a number of lines is added line by line, and lines
+    /// that are too wide for the screen scroll.
+    pub text: TextOptions,
 }
 impl DrawDevice {
     pub fn new(dev: Device, fps: usize) -> DrawDevice {
-        let layers: Arc<Mutex<LayerMap>> = Default::default();
+        let scene: Arc<Mutex<Scene>> = Default::default();
         let (cmd_sender, cmd_recver) = channel::<DrawCommand>();
         let (event_sender, event_receiver) = channel::<DrawEvent>();
-        let c_layers = layers.clone();
+        let c_scene = scene.clone();
         let (width, height) = (dev.width, dev.height);
         let thread = Some(std::thread::spawn(move || {
-            run_draw_device_thread(dev, c_layers, cmd_recver, event_sender, fps)
+            run_draw_device_thread(dev, c_scene, cmd_recver, event_sender, fps)
         }));
         DrawDevice {
             width,
             height,
-            layers,
+            scene,
             layer_counter: 0,
             thread,
             cmd_sender,
             event_receiver,
-            texter: TextRenderer::new_pixel_operator(),
+            texter: Arc::new(TextRenderer::new_pixel_operator()),
+            scroll: ScrollParams::default(),
+            text: TextOptions {
+                wrap: Wrap::None,
+                ..Default::default()
+            },
         }
     }
     fn destroy(&mut self) -> Option<Device> {
@@ -431,71 +746,71 @@ impl DrawDevice {
             (self.height as isize - bitmap.h as isize) / 2,
         )
     }
-    fn add_layer_locked(&mut self, layers: &mut MutexGuard<'_, LayerMap>, layer: DrawLayer) -> LayerId {
+    fn next_layer_id(&mut self) -> LayerId {
         self.layer_counter += 1;
-        let id = LayerId(self.layer_counter);
-        _ = layers.insert(
-            id,
-            DrawLayerState {
-                layer,
-                anim: AnimState {
-                    ticks: 0,
-                    next_update: Instant::now(),
-                },
-                scroll: ScrollState { x: 0 },
-            },
-        );
-        id
+        LayerId(self.layer_counter)
+    }
+    /// Start staging layer changes that are applied together, see `LayerTransaction`.
+    pub fn transaction(&mut self) -> LayerTransaction<'_> {
+        LayerTransaction { dev: self, ops: vec![] }
     }
     pub fn add_layer(&mut self, layer: DrawLayer) -> LayerId {
-        self.add_layer_locked(&mut self.layers.clone().lock(), layer)
+        let mut tx = self.transaction();
+        let id = tx.add_layer(layer);
+        tx.commit();
+        id
     }
     pub fn remove_layer(&mut self, id: LayerId) {
-        self.layers.lock().remove(&id);
+        let mut tx = self.transaction();
+        tx.remove_layer(id);
+        tx.commit();
     }
     pub fn remove_layers(&mut self, ids: &[LayerId]) {
-        let mut layers = self.layers.lock();
-        for id in ids {
-            layers.remove(id);
-        }
+        let mut tx = self.transaction();
+        tx.remove_layers(ids);
+        tx.commit();
     }
     pub fn clear_layers(&mut self) {
-        self.layers.lock().clear();
+        let mut tx = self.transaction();
+        tx.clear_layers();
+        tx.commit();
+    }
+    /// Get the properties of a layer, or `None` if it doesn't exist. These are updated by the render thread every
+    /// frame, so changes show up here a frame after they're committed.
+    pub fn layer_info(&self, id: LayerId) -> Option<LayerInfo> {
+        self.scene.lock().infos.get(&id).copied()
+    }
+    /// The whole screen.
+    pub fn screen_rect(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            w: self.width,
+            h: self.height,
+        }
+    }
+    /// A text layer using the device font and scroll parameters.
+    pub fn text_layer(&self, text: &str, align: TextAlign, rect: Rect) -> DrawLayer {
+        DrawLayer::Text {
+            text: text.to_string(),
+            font: self.texter.clone(),
+            align,
+            rect,
+            scroll: self.scroll,
+        }
     }
     pub fn font_line_height(&self) -> usize {
         self.texter.line_height()
     }
     pub fn add_text(&mut self, text: &str, x: Option<isize>, y: Option<isize>) -> Vec<LayerId> {
-        let layers = self.layers.clone();
-        let mut layers = layers.lock();
-        let bitmaps: Vec<_> = self.texter.render_lines(text).into_iter().map(Arc::new).collect();
-        let line_height = self.texter.line_height();
-        let center_y: isize = (self.height as isize - (line_height * bitmaps.len()) as isize) / 2;
-        bitmaps
-            .into_iter()
-            .enumerate()
-            .map(|(i, bitmap)| {
-                let y = y.unwrap_or(center_y) + (i * line_height) as isize;
-                if bitmap.w >= self.width {
-                    self.add_layer_locked(&mut layers, DrawLayer::Scroll { bitmap, y })
-                } else {
-                    let center = self.center_bitmap(&bitmap);
-                    self.add_layer_locked(
-                        &mut layers,
-                        DrawLayer::Image {
-                            bitmap,
-                            x: x.unwrap_or(center.0),
-                            y,
-                        },
-                    )
-                }
-            })
-            .collect()
+        let mut tx = self.transaction();
+        let ids = tx.add_text(text, x, y);
+        tx.commit();
+        ids
     }
     pub fn set_shift_mode(&mut self, mode: ShiftMode) {
         self.cmd_sender.send(DrawCommand::SetShiftMode(mode)).unwrap();
     }
-    // TODO: atomic layer updates instead of play/pause (use `layers` handle with guard? renderer can use `try_lock` to avoid delaying frames)
     pub fn play(&mut self) {
         self.cmd_sender.send(DrawCommand::Play).unwrap();
     }
@@ -512,6 +827,316 @@ impl Drop for DrawDevice {
     }
 }
 
+enum LayerOp {
+    Add(LayerId, DrawLayer, LayerOptions),
+    Remove(LayerId),
+    Clear,
+    Change(LayerId, LayerChange),
+    Restack(LayerId, Restack),
+}
+enum Restack {
+    Set(i32),
+    Raise,
+    Lower,
+    Front,
+    Back,
+}
+enum LayerChange {
+    Replace(DrawLayer),
+    Position(isize, isize),
+    Rect(Rect),
+    Bitmap(Arc<Bitmap>),
+    Frames(FrameSource),
+    Visible(bool),
+    Paused(bool),
+    Seek(usize),
+    Timeline(Option<Timeline>),
+    Text(String),
+}
+impl LayerChange {
+    // Whether the change does anything to layers of a kind, unlike e.g. seeking an image
+    fn applies_to(&self, kind: LayerKind) -> bool {
+        match self {
+            LayerChange::Bitmap(_) => matches!(kind, LayerKind::Image | LayerKind::Scroll),
+            LayerChange::Frames(_) | LayerChange::Paused(_) | LayerChange::Seek(_) => kind == LayerKind::Animation,
+            LayerChange::Text(_) => kind == LayerKind::Text,
+            _ => true,
+        }
+    }
+}
+
+/// Layer changes that are staged without touching the render thread, and then published all at once by `commit`.
+/// All changes in a transaction become visible in the same frame. Changes to layers that don't exist, or that don't
+/// apply to the kind of layer (like seeking an image), are ignored.
+#[must_use = "transactions do nothing unless committed"]
+pub struct LayerTransaction<'a> {
+    dev: &'a mut DrawDevice,
+    ops: Vec<LayerOp>,
+}
+impl LayerTransaction<'_> {
+    pub fn add_layer(&mut self, layer: DrawLayer) -> LayerId {
+        self.add_layer_with(layer, LayerOptions::default())
+    }
+    pub fn add_layer_with(&mut self, layer: DrawLayer, opts: LayerOptions) -> LayerId {
+        let id = self.dev.next_layer_id();
+        self.ops.push(LayerOp::Add(id, layer, opts));
+        id
+    }
+    pub fn remove_layer(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Remove(id));
+    }
+    pub fn remove_layers(&mut self, ids: &[LayerId]) {
+        self.ops.extend(ids.iter().map(|id| LayerOp::Remove(*id)));
+    }
+    pub fn clear_layers(&mut self) {
+        self.ops.push(LayerOp::Clear);
+    }
+    /// Replace a layer while keeping its animation and scroll progress.
+    pub fn update_layer(&mut self, id: LayerId, layer: DrawLayer) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Replace(layer)));
+    }
+    /// Move a layer. For scroll layers with a clip box, the box is moved.
+    pub fn set_position(&mut self, id: LayerId, x: isize, y: isize) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Position(x, y)));
+    }
+    /// Place a layer in a box. Text, clock and scroll layers fill it, groups with a clip box resize it, and other
+    /// layers are moved to its corner.
+    pub fn set_rect(&mut self, id: LayerId, rect: Rect) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Rect(rect)));
+    }
+    /// Run a layout keyed by layer ids again, and place the layers whose box has changed.
+    pub fn apply_layout(&mut self, layout: &mut Layout<LayerId>) {
+        for (id, rect) in layout.update() {
+            self.set_rect(id, rect);
+        }
+    }
+    /// Replace the bitmap of an image or scroll layer.
+    pub fn set_bitmap(&mut self, id: LayerId, bitmap: Arc<Bitmap>) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Bitmap(bitmap)));
+    }
+    /// Replace the text of a text layer. Scrolling continues undisturbed if the text is the same.
+    pub fn set_text(&mut self, id: LayerId, text: &str) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Text(text.to_string())));
+    }
+    /// Replace the frames of an animation layer.
+    pub fn set_frames(&mut self, id: LayerId, frames: FrameSource) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Frames(frames)));
+    }
+    pub fn set_visible(&mut self, id: LayerId, visible: bool) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Visible(visible)));
+    }
+    /// Pause or resume an animation layer. A resumed animation shows the current frame for its full delay.
+    pub fn set_paused(&mut self, id: LayerId, paused: bool) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Paused(paused)));
+    }
+    /// Jump to a frame of an animation layer and restart its loop count. Streams are restarted and decoded up to
+    /// the frame in the background, so seeking far into a stream takes a while.
+    pub fn seek(&mut self, id: LayerId, frame: usize) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Seek(frame)));
+    }
+    /// Animate the position and visibility of a layer, replacing any previous timeline. The timeline starts in the
+    /// first frame rendered after committing.
+    pub fn set_timeline(&mut self, id: LayerId, timeline: Timeline) {
+        self.ops
+            .push(LayerOp::Change(id, LayerChange::Timeline(Some(timeline))));
+    }
+    /// Stop the timeline of a layer, keeping its current position and visibility.
+    pub fn stop_timeline(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Change(id, LayerChange::Timeline(None)));
+    }
+    pub fn set_z(&mut self, id: LayerId, z: i32) {
+        self.ops.push(LayerOp::Restack(id, Restack::Set(z)));
+    }
+    /// Increase the `z` of a layer by one.
+    pub fn raise_layer(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Restack(id, Restack::Raise));
+    }
+    /// Decrease the `z` of a layer by one.
+    pub fn lower_layer(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Restack(id, Restack::Lower));
+    }
+    /// Put a layer above all other current layers.
+    pub fn bring_to_front(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Restack(id, Restack::Front));
+    }
+    /// Put a layer below all other current layers.
+    pub fn send_to_back(&mut self, id: LayerId) {
+        self.ops.push(LayerOp::Restack(id, Restack::Back));
+    }
+    pub fn add_text(&mut self, text: &str, x: Option<isize>, y: Option<isize>) -> Vec<LayerId> {
+        let opts = self.dev.text;
+        if opts.wrap != Wrap::None || opts.max_lines.is_some() {
+            // Laid out as a single box that fits on the screen
+            let width = opts
+                .width
+                .unwrap_or_else(|| self.dev.width.saturating_sub(x.unwrap_or(0).max(0) as usize));
+            let opts = TextOptions {
+                width: Some(width),
+                ..opts
+            };
+            if self.dev.texter.style().halo != Halo::None {
+                return vec![self.add_halo_text(text, &opts, x, y)];
+            }
+            let bitmap = Arc::new(self.dev.texter.render(text, &opts));
+            let center = self.dev.center_bitmap(&bitmap);
+            return vec![self.add_layer(DrawLayer::Image {
+                bitmap,
+                x: x.unwrap_or(center.0),
+                y: y.unwrap_or(center.1),
+            })];
+        }
+        if self.dev.texter.style().halo != Halo::None {
+            return vec![self.add_halo_text(text, &opts, x, y)];
+        }
+        let bitmaps: Vec<_> = self.dev.texter.render_lines(text).into_iter().map(Arc::new).collect();
+        let line_height = self.dev.texter.line_height();
+        let center_y: isize = (self.dev.height as isize - (line_height * bitmaps.len()) as isize) / 2;
+        bitmaps
+            .into_iter()
+            .enumerate()
+            .map(|(i, bitmap)| {
+                let y = y.unwrap_or(center_y) + (i * line_height) as isize;
+                if bitmap.w >= self.dev.width {
+                    self.add_layer(DrawLayer::Scroll {
+                        bitmap,
+                        x: 0,
+                        y,
+                        params: self.dev.scroll,
+                    })
+                } else {
+                    let space = (self.dev.width - bitmap.w) as isize;
+                    let aligned = match opts.align {
+                        TextAlign::Left | TextAlign::Justify => 0,
+                        TextAlign::Center => space / 2,
+                        TextAlign::Right => space,
+                    };
+                    self.add_layer(DrawLayer::Image {
+                        bitmap,
+                        x: x.unwrap_or(aligned),
+                        y,
+                    })
+                }
+            })
+            .collect()
+    }
+    // Text with a halo is added as a text layer, which masks the halo so that it clears what's below it. The box is
+    // the size of the laid out lines, or spans the screen where the text isn't positioned.
+    fn add_halo_text(&mut self, text: &str, opts: &TextOptions, x: Option<isize>, y: Option<isize>) -> LayerId {
+        let texter = &self.dev.texter;
+        let text = match opts.width {
+            Some(_) => texter
+                .layout(text, opts)
+                .into_iter()
+                .map(|l| l.text)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => text.to_string(),
+        };
+        let lines = text.split('\n').count();
+        let (w, h) = (self.dev.width, self.dev.height);
+        let (rect_x, rect_w, align) = match (opts.width, x) {
+            (Some(width), x) => {
+                let (left, right) = texter.style().halo.pad();
+                let box_w = width + (left + right) as usize;
+                (x.unwrap_or((w as isize - box_w as isize) / 2), box_w, opts.align)
+            }
+            (None, Some(x)) => (x, w.saturating_sub(x.max(0) as usize), TextAlign::Left),
+            (None, None) => (0, w, opts.align),
+        };
+        let (rect_y, rect_h) = match y {
+            Some(y) => (y, lines * texter.line_height()),
+            None => (0, h),
+        };
+        let layer = self.dev.text_layer(
+            &text,
+            align,
+            Rect {
+                x: rect_x,
+                y: rect_y,
+                w: rect_w,
+                h: rect_h,
+            },
+        );
+        self.add_layer(layer)
+    }
+    /// Draw markup with the device font and text alignment, centered on the screen unless positioned. Markup isn't
+    /// wrapped, and scrolls if it's wider than the screen. The font's halo isn't drawn, since markup is drawn on top of
+    /// other layers like an image.
+    pub fn add_markup(
+        &mut self,
+        markup: &str,
+        icons: &HashMap<String, Bitmap>,
+        x: Option<isize>,
+        y: Option<isize>,
+    ) -> anyhow::Result<LayerId> {
+        let bitmap = Arc::new(self.dev.texter.render_markup(markup, icons, self.dev.text.align)?);
+        let center = self.dev.center_bitmap(&bitmap);
+        let y = y.unwrap_or(center.1);
+        if bitmap.w > self.dev.width {
+            return Ok(self.add_layer(DrawLayer::Scroll {
+                bitmap,
+                x: 0,
+                y,
+                params: self.dev.scroll,
+            }));
+        }
+        Ok(self.add_layer(DrawLayer::Image {
+            bitmap,
+            x: x.unwrap_or(center.0),
+            y,
+        }))
+    }
+    pub fn commit(self) {
+        self.apply(None);
+    }
+    /// Commit, and switch from what is currently shown to the result using a transition.
+    pub fn commit_with_transition(self, transition: Transition) {
+        self.apply(Some(transition));
+    }
+    fn apply(self, transition: Option<Transition>) {
+        let mut scene = self.dev.scene.lock();
+        if transition.is_some() {
+            scene.transition = transition;
+        }
+        scene.ops.extend(self.ops);
+    }
+}
+
+// Apply the changes of committed transactions to the render thread's layers
+fn apply_ops(layers: &mut LayerMap, ops: Vec<LayerOp>) {
+    for op in ops {
+        match op {
+            LayerOp::Add(id, layer, opts) => _ = layers.insert(id, DrawLayerState::new(layer, opts)),
+            LayerOp::Remove(id) => _ = layers.remove(&id),
+            LayerOp::Clear => layers.clear(),
+            LayerOp::Change(id, change) => {
+                if let Some(state) = layers.get_mut(&id) {
+                    state.apply(change);
+                }
+            }
+            LayerOp::Restack(id, restack) => {
+                let others = layers
+                    .iter()
+                    .filter(|(other, _)| **other != id)
+                    .map(|(_, state)| state.z);
+                let z = match restack {
+                    Restack::Front => others.max().map(|z| z.saturating_add(1)),
+                    Restack::Back => others.min().map(|z| z.saturating_sub(1)),
+                    _ => None,
+                };
+                if let Some(state) = layers.get_mut(&id) {
+                    state.z = match restack {
+                        Restack::Set(z) => z,
+                        Restack::Raise => state.z.saturating_add(1),
+                        Restack::Lower => state.z.saturating_sub(1),
+                        Restack::Front | Restack::Back => z.unwrap_or(state.z),
+                    };
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 #[rustfmt::skip]
 #[test]
@@ -533,3 +1158,105 @@ fn shift_pos() {
         (0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1),
     ]);
 }
+
+#[cfg(test)]
+#[test]
+fn layer_changes_keep_state() {
+    let bitmap = Arc::new(Bitmap::new(200, 10, true));
+    let mut state = DrawLayerState::new(
+        DrawLayer::Scroll {
+            bitmap,
+            x: 0,
+            y: 0,
+            params: Default::default(),
+        },
+        LayerOptions {
+            z: 3,
+            ..Default::default()
+        },
+    );
+    state.scroll.offset = 42.0;
+    state.apply(LayerChange::Position(5, 20));
+    state.apply(LayerChange::Bitmap(Arc::new(Bitmap::new(300, 12, true))));
+    state.apply(LayerChange::Visible(false));
+    assert_eq!(state.scroll.offset, 42.0);
+    assert_eq!(
+        state.info(),
+        LayerInfo {
+            kind: LayerKind::Scroll,
+            x: 5,
+            y: 20,
+            w: 300,
+            h: 12,
+            visible: false,
+            z: 3,
+        }
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn layer_change_kinds() {
+    let bitmap = || Arc::new(Bitmap::new(1, 1, true));
+    assert!(LayerChange::Bitmap(bitmap()).applies_to(LayerKind::Scroll));
+    assert!(!LayerChange::Bitmap(bitmap()).applies_to(LayerKind::Text));
+    assert!(LayerChange::Seek(0).applies_to(LayerKind::Animation));
+    assert!(!LayerChange::Seek(0).applies_to(LayerKind::Image));
+    assert!(!LayerChange::Paused(true).applies_to(LayerKind::Image));
+    assert!(!LayerChange::Text("a".into()).applies_to(LayerKind::Clock));
+    assert!(LayerChange::Position(1, 2).applies_to(LayerKind::Clock));
+    assert!(LayerChange::Visible(false).applies_to(LayerKind::Group));
+}
+
+#[cfg(test)]
+#[test]
+fn mismatched_layer_change() {
+    let mut state = DrawLayerState::new(
+        DrawLayer::Image {
+            bitmap: Arc::new(Bitmap::new(1, 1, true)),
+            x: 0,
+            y: 0,
+        },
+        LayerOptions::default(),
+    );
+    let info = state.info();
+    state.apply(LayerChange::Text("a".into()));
+    state.apply(LayerChange::Seek(3));
+    assert_eq!(state.info(), info);
+}
+
+#[cfg(test)]
+#[test]
+fn group_clips_children() {
+    let image = |x, y| DrawLayer::Image {
+        bitmap: Arc::new(Bitmap::new(4, 4, true)),
+        x,
+        y,
+    };
+    let mut state = DrawLayerState::new(
+        DrawLayer::Group {
+            layers: vec![image(0, 0), image(6, 2)],
+            x: 10,
+            y: 5,
+            clip: Some(Rect { x: 2, y: 0, w: 6, h: 4 }),
+        },
+        LayerOptions::default(),
+    );
+    let mut screen = Bitmap::new(32, 16, false);
+    state.render(&mut screen, (0, 0), Instant::now(), 0);
+    let on: Vec<_> = (0..screen.w * screen.h)
+        .filter(|&i| screen.data[i])
+        .map(|i| (i % screen.w, i / screen.w))
+        .collect();
+    // Columns 12-17 of the group are visible: 12-13 from the first child and 16-17 from the second
+    let expected: Vec<_> = (5..9)
+        .flat_map(|y| {
+            [12, 13, 16, 17]
+                .into_iter()
+                .filter(move |&x| x < 16 || y >= 7)
+                .map(move |x| (x, y))
+        })
+        .collect();
+    assert_eq!(on, expected);
+    assert_eq!(state.info().w, 6);
+}
@@ -0,0 +1,360 @@
+// Rich text markup, where spans of text are styled with tags like `[b]bold[/b]` and icons are placed inline.
+//
+// Tags:
+// - `[b]...[/b]` bold, drawn by smearing glyphs one pixel to the right
+// - `[inv]...[/inv]` inverted, drawn dark on a lit box that fills the line
+// - `[u]...[/u]` underlined
+// - `[size=N]...[/size]` text with a line height of N pixels
+// - `[icon=name]` bitmap from the icons given when rendering, sitting on the baseline
+// `[[` is a literal `[`, and `escape_markup` escapes text so it's drawn as it is.
+
+use crate::{TextAlign, TextRenderer, text::PlacedGlyph};
+use anyhow::bail;
+use ggoled_lib::Bitmap;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+struct Style {
+    bold: bool,
+    invert: bool,
+    underline: bool,
+    // Line height in pixels, or the font's own if unset
+    size: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Text(String, Style),
+    Icon(String, Style),
+    Break,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tag {
+    Bold,
+    Invert,
+    Underline,
+    Size,
+}
+
+fn parse(markup: &str) -> anyhow::Result<Vec<Piece>> {
+    let mut pieces = vec![];
+    let mut stack: Vec<(Tag, Style)> = vec![];
+    let mut style = Style::default();
+    let mut text = String::new();
+    let mut rest = markup;
+    // Push the text so far as a piece of its own, when the style changes or something else comes in between
+    let flush = |text: &mut String, pieces: &mut Vec<Piece>, style: Style| {
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(text), style));
+        }
+    };
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '[' if rest.starts_with('[') => {
+                text.push('[');
+                rest = &rest[1..];
+            }
+            '[' => {
+                let Some(end) = rest.find(']') else {
+                    bail!("Unterminated tag");
+                };
+                let tag = &rest[..end];
+                rest = &rest[end + 1..];
+                flush(&mut text, &mut pieces, style);
+                if let Some(name) = tag.strip_prefix('/') {
+                    let close = match name {
+                        "b" => Tag::Bold,
+                        "inv" => Tag::Invert,
+                        "u" => Tag::Underline,
+                        "size" => Tag::Size,
+                        _ => bail!("Unknown closing tag [{tag}]"),
+                    };
+                    match stack.pop() {
+                        Some((open, prev)) if open == close => style = prev,
+                        _ => bail!("Closing tag [{tag}] doesn't match the open tag"),
+                    }
+                    continue;
+                }
+                if let Some(name) = tag.strip_prefix("icon=") {
+                    pieces.push(Piece::Icon(name.to_string(), style));
+                    continue;
+                }
+                let prev = style;
+                let open = match tag {
+                    "b" => {
+                        style.bold = true;
+                        Tag::Bold
+                    }
+                    "inv" => {
+                        style.invert = true;
+                        Tag::Invert
+                    }
+                    "u" => {
+                        style.underline = true;
+                        Tag::Underline
+                    }
+                    _ => match tag.strip_prefix("size=").and_then(|n| n.parse().ok()) {
+                        Some(size) if size > 0 => {
+                            style.size = Some(size);
+                            Tag::Size
+                        }
+                        _ => bail!("Unknown tag [{tag}]"),
+                    },
+                };
+                stack.push((open, prev));
+            }
+            '\r' => {}
+            '\n' => {
+                flush(&mut text, &mut pieces, style);
+                pieces.push(Piece::Break);
+            }
+            c => text.push(c),
+        }
+    }
+    if !stack.is_empty() {
+        bail!("Unclosed tag");
+    }
+    flush(&mut text, &mut pieces, style);
+    Ok(pieces)
+}
+
+/// Escape text so that it's drawn as it is when used in markup.
+pub fn escape_markup(text: &str) -> String {
+    text.replace('[', "[[")
+}
+
+enum Content<'a> {
+    Glyphs(Vec<PlacedGlyph<'a>>),
+    Icon(&'a Bitmap),
+}
+
+// Styled span placed on a line, with the pen going from `start` to `end` pixels from the left. The span reaches from
+// `left` to `right`, which covers its pixels and the way the pen moved forward, but not back like with negative
+// tracking.
+struct Span<'a> {
+    start: i32,
+    end: i32,
+    left: i32,
+    right: i32,
+    style: Style,
+    content: Content<'a>,
+}
+impl Span<'_> {
+    // Call `put` for every lit pixel of the glyphs or icon, with the baseline at `baseline`
+    fn draw(&self, baseline: i32, mut put: impl FnMut(i32, i32)) {
+        match &self.content {
+            Content::Glyphs(glyphs) => {
+                for (i, glyph) in glyphs.iter().enumerate() {
+                    let offset = if self.style.bold { i as i32 } else { 0 };
+                    glyph.draw(|px, py| {
+                        put(px + offset, py);
+                        if self.style.bold {
+                            put(px + offset + 1, py);
+                        }
+                    });
+                }
+            }
+            Content::Icon(icon) => {
+                let icon_top = baseline - icon.h as i32;
+                for iy in 0..icon.h {
+                    for ix in 0..icon.w {
+                        if icon.get(ix as isize, iy as isize) {
+                            put(self.start + ix as i32, icon_top + iy as i32);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Line of placed spans, with the baseline at `baseline` pixels from the top. Its spans reach from `left` to `right`.
+struct MarkupLine<'a> {
+    spans: Vec<Span<'a>>,
+    baseline: i32,
+    height: usize,
+    left: i32,
+    right: i32,
+}
+
+impl TextRenderer {
+    // Zoom of the fonts for a span style
+    fn zoom(&self, style: Style) -> f32 {
+        style
+            .size
+            .map_or(1.0, |size| size as f32 / self.natural_line_height() as f32)
+    }
+
+    fn place_markup<'a>(&'a self, pieces: &[Piece], icons: &'a HashMap<String, Bitmap>) -> MarkupLine<'a> {
+        // Every line is at least as tall as a line of plain text, and fits its tallest span
+        let mut ascent = self.baseline();
+        let mut descent = self.line_height() as f32 - ascent;
+        for piece in pieces {
+            match piece {
+                Piece::Text(_, style) => {
+                    let zoom = self.zoom(*style);
+                    ascent = ascent.max(self.ascent(zoom));
+                    descent = descent.max(self.descent(zoom));
+                }
+                Piece::Icon(name, _) => ascent = ascent.max(icons[name].h as f32),
+                Piece::Break => {}
+            }
+        }
+        let baseline = ascent.round();
+        let mut pen = 0.0;
+        let spans: Vec<Span> = pieces
+            .iter()
+            .filter_map(|piece| {
+                let start = pen;
+                let (style, content) = match piece {
+                    Piece::Text(text, style) => {
+                        let glyphs = self.place_line(text, self.zoom(*style), baseline, &mut pen);
+                        if style.bold {
+                            // Every glyph gets wider, so they're spaced apart as many pixels
+                            pen += glyphs.len() as f32;
+                        }
+                        (*style, Content::Glyphs(glyphs))
+                    }
+                    Piece::Icon(name, style) => {
+                        let icon = &icons[name];
+                        pen += icon.w as f32;
+                        (*style, Content::Icon(icon))
+                    }
+                    Piece::Break => return None,
+                };
+                let (start, end) = (start.round() as i32, pen.round() as i32);
+                let mut span = Span {
+                    start,
+                    end,
+                    left: start,
+                    right: start.max(end),
+                    style,
+                    content,
+                };
+                let (mut left, mut right) = (span.left, span.right);
+                span.draw(baseline as i32, |px, _| {
+                    left = left.min(px);
+                    right = right.max(px + 1);
+                });
+                (span.left, span.right) = (left, right);
+                Some(span)
+            })
+            .collect();
+        let (left, right) = spans.iter().fold((0, 0), |(left, right), span| {
+            (left.min(span.left), right.max(span.right))
+        });
+        MarkupLine {
+            spans,
+            baseline: baseline as i32,
+            height: (baseline + descent).ceil() as usize,
+            left,
+            right,
+        }
+    }
+
+    /// Draw markup, with each line aligned within the widest one. Icons are looked up by name in `icons`.
+    pub fn render_markup(
+        &self,
+        markup: &str,
+        icons: &HashMap<String, Bitmap>,
+        align: TextAlign,
+    ) -> anyhow::Result<Bitmap> {
+        let pieces = parse(markup)?;
+        for piece in &pieces {
+            if let Piece::Icon(name, _) = piece
+                && !icons.contains_key(name)
+            {
+                bail!("Unknown icon {name}");
+            }
+        }
+        let lines: Vec<MarkupLine> = pieces
+            .split(|p| *p == Piece::Break)
+            .map(|pieces| self.place_markup(pieces, icons))
+            .collect();
+        // Lines are measured by how far their spans reach, and shifted right if they reach left of where they start
+        let width = |line: &MarkupLine| (line.right - line.left) as usize;
+        let w = lines.iter().map(width).max().unwrap_or(0);
+        let mut bitmap = Bitmap::new(w, lines.iter().map(|l| l.height).sum(), false);
+        let mut top = 0;
+        for line in &lines {
+            let space = (w - width(line)) as i32;
+            let x = match align {
+                TextAlign::Left | TextAlign::Justify => 0,
+                TextAlign::Center => space / 2,
+                TextAlign::Right => space,
+            } - line.left;
+            for span in &line.spans {
+                let on = !span.style.invert;
+                if span.style.invert {
+                    bitmap.fill_rect(
+                        (x + span.left) as isize,
+                        top as isize,
+                        (span.right - span.left) as usize,
+                        line.height,
+                        true,
+                    );
+                }
+                let mut put = |px: i32, py: i32| bitmap.set((x + px) as isize, (top + py) as isize, on);
+                span.draw(line.baseline, &mut put);
+                if span.style.underline {
+                    for px in span.start.min(span.end)..span.start.max(span.end) {
+                        put(px, line.baseline + 1);
+                    }
+                }
+            }
+            top += line.height as i32;
+        }
+        Ok(bitmap)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn markup() {
+    let bold = Style {
+        bold: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        parse("a [b]b[[c[/b]\nd").unwrap(),
+        [
+            Piece::Text("a ".into(), Style::default()),
+            Piece::Text("b[c".into(), bold),
+            Piece::Break,
+            Piece::Text("d".into(), Style::default()),
+        ]
+    );
+    assert!(parse("[b]a").is_err() && parse("[b]a[/u]").is_err() && parse("[x]").is_err());
+    assert_eq!(
+        parse(&escape_markup("[b]")).unwrap(),
+        [Piece::Text("[b]".into(), Style::default())]
+    );
+
+    let font = TextRenderer::new_pixel_operator();
+    let icons = HashMap::from([("dot".to_string(), Bitmap::new(3, 3, true))]);
+    let render = |markup| font.render_markup(markup, &icons, TextAlign::Left).unwrap();
+    let plain = render("abc");
+    assert_eq!(plain.h, font.line_height());
+    // Bold text is a pixel wider for every glyph
+    assert_eq!(render("[b]abc[/b]").w, plain.w + 3);
+    // Inverted text has a lit box around it
+    let inverted = render("[inv]abc[/inv]");
+    assert!(inverted.get(0, 0) && !plain.get(0, 0));
+    // The icon sits on the baseline
+    let icon = render("[icon=dot]");
+    let baseline = font.ascent(1.0).round() as isize;
+    assert!(icon.w == 3 && icon.get(0, baseline - 1) && !icon.get(0, baseline));
+    // Bigger text makes the line taller
+    assert_eq!(render("a[size=32]b[/size]").h, 32);
+    assert!(font.render_markup("[icon=nope]", &icons, TextAlign::Left).is_err());
+    // Negative tracking moves the pen back past where the line starts, which is measured like plain text
+    let tight = TextRenderer::new_pixel_operator().with_style(crate::TextStyle {
+        tracking: -20,
+        ..Default::default()
+    });
+    let markup = |markup| tight.render_markup(markup, &icons, TextAlign::Right).unwrap();
+    assert_eq!(markup("abc").w, tight.render("abc", &Default::default()).w);
+    assert!(markup("[inv]ab[/inv]\n[b][u]abc[/u][/b]").data.any());
+}
@@ -0,0 +1,199 @@
+// Scrolling of bitmaps that don't fit in their box.
+
+use crate::Rect;
+use ggoled_lib::Bitmap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ScrollDirection {
+    /// Content moves towards the left
+    #[default]
+    Left,
+    Right,
+    Up,
+    Down,
+}
+impl ScrollDirection {
+    fn horizontal(self) -> bool {
+        matches!(self, ScrollDirection::Left | ScrollDirection::Right)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ScrollMode {
+    /// Repeat the content endlessly, separated by `gap`
+    #[default]
+    Loop,
+    /// Move back and forth between showing the start and the end of the content
+    Bounce,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScrollParams {
+    /// Speed in pixels per second
+    pub speed: f32,
+    pub direction: ScrollDirection,
+    pub mode: ScrollMode,
+    /// Empty space between repetitions when looping
+    pub gap: usize,
+    /// How long to stand still at the start of each cycle, and at both ends when bouncing
+    pub pause: Duration,
+    /// Box that the content scrolls within, which replaces the layer position. By default the box starts at the layer
+    /// position and extends to the edge of the screen along the scroll direction.
+    pub clip: Option<Rect>,
+}
+impl Default for ScrollParams {
+    fn default() -> Self {
+        Self {
+            speed: 30.0,
+            direction: ScrollDirection::Left,
+            mode: ScrollMode::Loop,
+            gap: 30,
+            pause: Duration::ZERO,
+            clip: None,
+        }
+    }
+}
+
+pub(crate) struct ScrollState {
+    /// Distance scrolled in the current cycle
+    pub offset: f32,
+    // Moving towards the end of the content when bouncing
+    forward: bool,
+    paused_until: Option<Instant>,
+    last_update: Option<Instant>,
+}
+impl ScrollState {
+    pub fn new() -> Self {
+        Self {
+            offset: 0.0,
+            forward: true,
+            paused_until: None,
+            last_update: None,
+        }
+    }
+
+    fn update(&mut self, params: &ScrollParams, content: usize, view: usize, time: Instant) {
+        let Some(last) = self.last_update.replace(time) else {
+            self.paused_until = Some(time + params.pause);
+            return;
+        };
+        let from = match self.paused_until {
+            Some(until) if time < until => return,
+            Some(until) => until.max(last),
+            None => last,
+        };
+        let step = params.speed.max(0.0) * time.duration_since(from).as_secs_f32();
+        let mut pause = false;
+        match params.mode {
+            ScrollMode::Loop => {
+                let period = (content + params.gap).max(1) as f32;
+                self.offset += step;
+                if self.offset >= period {
+                    pause = !params.pause.is_zero();
+                    self.offset = if pause { 0.0 } else { self.offset % period };
+                }
+            }
+            ScrollMode::Bounce => {
+                let range = content.saturating_sub(view) as f32;
+                if self.forward {
+                    self.offset += step;
+                    if self.offset >= range {
+                        (self.offset, self.forward, pause) = (range, false, true);
+                    }
+                } else {
+                    self.offset -= step;
+                    if self.offset <= 0.0 {
+                        (self.offset, self.forward, pause) = (0.0, true, true);
+                    }
+                }
+            }
+        }
+        self.paused_until = pause.then(|| time + params.pause);
+    }
+
+    /// Advance the scroll and draw the visible part of `bitmap` to `screen`, only where `mask` is set if given. `pos` is
+    /// the layer position, and `shift` is added to the final box position.
+    pub fn render(
+        &mut self,
+        screen: &mut Bitmap,
+        (bitmap, mask): (&Bitmap, Option<&Bitmap>),
+        pos: (isize, isize),
+        shift: (isize, isize),
+        params: &ScrollParams,
+        time: Instant,
+    ) {
+        let horizontal = params.direction.horizontal();
+        let to_edge = |screen_len: usize, p: isize| (screen_len as isize - p).max(0) as usize;
+        let area = params.clip.unwrap_or(Rect {
+            x: pos.0,
+            y: pos.1,
+            w: if horizontal { to_edge(screen.w, pos.0) } else { bitmap.w },
+            h: if horizontal { bitmap.h } else { to_edge(screen.h, pos.1) },
+        });
+        let (content, view) = if horizontal {
+            (bitmap.w, area.w)
+        } else {
+            (bitmap.h, area.h)
+        };
+        self.update(params, content, view, time);
+
+        let offset = self.offset.round() as isize;
+        let reverse = !matches!(params.direction, ScrollDirection::Left | ScrollDirection::Up);
+        let positions: Vec<isize> = match params.mode {
+            ScrollMode::Loop => {
+                let period = (content + params.gap).max(1) as isize;
+                let start = if reverse { offset - period } else { -offset };
+                (0..)
+                    .map(|i| start + i * period)
+                    .take_while(|p| *p < view as isize)
+                    .collect()
+            }
+            ScrollMode::Bounce => {
+                let range = content.saturating_sub(view) as isize;
+                vec![if reverse { offset - range } else { -offset }]
+            }
+        };
+        let scrolled = |bitmap: &Bitmap| {
+            let mut boxed = Bitmap::new(area.w, area.h, false);
+            for p in &positions {
+                if horizontal {
+                    boxed.blit(bitmap, *p, 0, false);
+                } else {
+                    boxed.blit(bitmap, 0, *p, false);
+                }
+            }
+            boxed
+        };
+        let (x, y) = (area.x + shift.0, area.y + shift.1);
+        match mask {
+            Some(mask) => screen.blit_masked(&scrolled(bitmap), &scrolled(mask), x, y),
+            None => screen.blit(&scrolled(bitmap), x, y, false),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn bounce_pauses_at_ends() {
+    let params = ScrollParams {
+        speed: 100.0,
+        mode: ScrollMode::Bounce,
+        pause: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let t0 = Instant::now();
+    let at = |ms| t0 + Duration::from_millis(ms);
+    let mut state = ScrollState::new();
+    state.update(&params, 150, 100, t0);
+    state.update(&params, 150, 100, at(400));
+    assert_eq!(state.offset, 0.0);
+    state.update(&params, 150, 100, at(700));
+    assert_eq!(state.offset, 20.0);
+    state.update(&params, 150, 100, at(1500));
+    assert_eq!(state.offset, 50.0);
+    state.update(&params, 150, 100, at(1900));
+    assert_eq!(state.offset, 50.0);
+    state.update(&params, 150, 100, at(2100));
+    assert_eq!(state.offset, 40.0);
+}
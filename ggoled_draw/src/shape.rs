@@ -0,0 +1,75 @@
+// Shaping and bidirectional reordering of text, so that scripts that join, reorder or combine characters come out
+// right.
+
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+use std::ops::Range;
+use unicode_bidi::BidiInfo;
+
+/// Byte ranges of a line with the same direction, in visual order from left to right, and whether they are
+/// right-to-left.
+pub(crate) fn visual_runs(line: &str) -> Vec<(Range<usize>, bool)> {
+    let bidi = BidiInfo::new(line, None);
+    let Some(para) = bidi.paragraphs.first() else {
+        return vec![];
+    };
+    if !bidi.has_rtl() {
+        return vec![(0..line.len(), false)];
+    }
+    let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+    runs.into_iter()
+        .map(|run| {
+            let rtl = levels[run.start].is_rtl();
+            (run, rtl)
+        })
+        .collect()
+}
+
+/// Glyph produced by shaping, positioned in font units.
+pub(crate) struct ShapedGlyph {
+    pub id: u16,
+    /// Byte index of the first character that the glyph is for
+    pub cluster: usize,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// Shape a run of text that uses one font and direction. The glyphs are in visual order from left to right.
+pub(crate) fn shape(face: &Face, text: &str, rtl: bool) -> Vec<ShapedGlyph> {
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(if rtl {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    });
+    buffer.guess_segment_properties();
+    let output = rustybuzz::shape(face, &[], buffer);
+    output
+        .glyph_infos()
+        .iter()
+        .zip(output.glyph_positions())
+        .map(|(info, pos)| ShapedGlyph {
+            id: info.glyph_id as u16,
+            cluster: info.cluster as usize,
+            x_advance: pos.x_advance,
+            x_offset: pos.x_offset,
+            y_offset: pos.y_offset,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn bidi_and_shaping() {
+    // Hebrew is reordered into a right-to-left run between the left-to-right ones
+    let line = "ab אבג cd";
+    let runs = visual_runs(line);
+    let texts: Vec<(&str, bool)> = runs.iter().map(|(r, rtl)| (&line[r.clone()], *rtl)).collect();
+    assert_eq!(texts, [("ab ", false), ("אבג", true), (" cd", false)]);
+    // Right-to-left glyphs come out in visual order, so the last character comes first
+    let font = Face::from_slice(include_bytes!("../fonts/PixelOperator.ttf"), 0).unwrap();
+    let glyphs = shape(&font, "abc", true);
+    assert_eq!(glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>(), [2, 1, 0]);
+    assert!(glyphs.iter().all(|g| g.x_advance > 0));
+}
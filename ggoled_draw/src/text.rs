@@ -0,0 +1,869 @@
+// Text rendering and layout, and text layers, which keep their rasterized lines and scroll progress until the text
+// changes.
+
+#[cfg(feature = "shaping")]
+use crate::shape;
+use crate::{Rect, font::BitmapFont, scroll::ScrollParams, scroll::ScrollState};
+use anyhow::bail;
+use ggoled_lib::Bitmap;
+use lru::LruCache;
+use parking_lot::Mutex;
+use rusttype::{Font, Point, Scale, ScaledGlyph, point};
+use self_cell::self_cell;
+use std::{num::NonZeroUsize, ops::Range, path::PathBuf, sync::Arc, time::Instant};
+
+/// Horizontal alignment of text lines within their box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+    /// Stretch the spaces of wrapped lines to fill the box. Other lines are aligned left.
+    Justify,
+}
+
+/// How lines that are wider than their box are broken up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Wrap {
+    /// Don't break lines, but cut them off with an ellipsis
+    None,
+    /// Break between words, and within words that don't fit on a line by themselves
+    #[default]
+    Word,
+    /// Break between any characters
+    Char,
+}
+
+/// Box that text is laid out in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct TextOptions {
+    /// Width of the box, or the width of the widest line if unset
+    pub width: Option<usize>,
+    pub wrap: Wrap,
+    pub align: TextAlign,
+    /// Maximum number of lines, where the last line is cut off with an ellipsis if there's more text
+    pub max_lines: Option<usize>,
+}
+
+/// Border drawn around text in the inverse color, so that it stays readable on top of other layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Halo {
+    #[default]
+    None,
+    /// 1 pixel outline all around
+    Outline,
+    /// 1 pixel drop shadow to the bottom right
+    Shadow,
+}
+impl Halo {
+    // Pixels that the halo sticks out to the left and right of the text
+    pub(crate) fn pad(self) -> (i32, i32) {
+        match self {
+            Halo::None => (0, 0),
+            Halo::Outline => (1, 1),
+            Halo::Shadow => (0, 1),
+        }
+    }
+    // Offsets of the halo pixels around each text pixel
+    fn offsets(self) -> &'static [(isize, isize)] {
+        match self {
+            Halo::None => &[],
+            Halo::Outline => &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)],
+            Halo::Shadow => &[(1, 1)],
+        }
+    }
+}
+
+/// How glyphs are drawn and spaced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    pub halo: Halo,
+    /// Extra pixels between characters, which may be negative
+    pub tracking: i32,
+    /// Height of lines, or the height that fits the fonts if unset
+    pub line_height: Option<usize>,
+    /// Coverage above which pixels of TTF and OTF glyphs are lit, from 0 to 1
+    pub threshold: f32,
+}
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            halo: Halo::None,
+            tracking: 0,
+            line_height: None,
+            threshold: 0.5,
+        }
+    }
+}
+
+// Line of laid out text
+pub(crate) struct Line {
+    pub text: String,
+    // Whether the line was wrapped, and should be stretched when justifying
+    wrapped: bool,
+}
+
+// TTF or OTF font, parsed from the font file that it borrows
+struct VectorFont<'a> {
+    font: Font<'a>,
+    #[cfg(feature = "shaping")]
+    shaper: rustybuzz::Face<'a>,
+}
+
+self_cell!(
+    // Font file together with the fonts parsed from it, so that it's only kept and parsed once
+    struct VectorData {
+        owner: Arc<[u8]>,
+        #[covariant]
+        dependent: VectorFont,
+    }
+);
+
+enum Face {
+    Vector { data: VectorData, size: f32 },
+    Bitmap(BitmapFont),
+}
+impl Face {
+    fn vector(data: Vec<u8>, size: f32) -> Option<Self> {
+        let data = VectorData::try_new(Arc::from(data), |data| {
+            Ok::<_, ()>(VectorFont {
+                font: Font::try_from_bytes(data).ok_or(())?,
+                #[cfg(feature = "shaping")]
+                shaper: rustybuzz::Face::from_slice(data, 0).ok_or(())?,
+            })
+        })
+        .ok()?;
+        Some(Face::Vector { data, size })
+    }
+    fn has_glyph(&self, c: char) -> bool {
+        match self {
+            Face::Vector { data, .. } => data.borrow_dependent().font.glyph(c).id().0 != 0,
+            Face::Bitmap(font) => font.has_glyph(c),
+        }
+    }
+    // Distance from the top of the line to the baseline, and from the baseline to the bottom, when zoomed
+    fn ascent_descent(&self, zoom: f32) -> (f32, f32) {
+        match self {
+            Face::Vector { data, size } => {
+                let v_metrics = data.borrow_dependent().font.v_metrics(Scale::uniform(size * zoom));
+                (v_metrics.ascent, -v_metrics.descent)
+            }
+            Face::Bitmap(font) => {
+                let scale = bitmap_scale(zoom) as f32;
+                (font.ascent as f32 * scale, font.descent as f32 * scale)
+            }
+        }
+    }
+}
+
+// Bitmap fonts are only zoomed by whole pixels, to keep them crisp
+fn bitmap_scale(zoom: f32) -> usize {
+    zoom.round().max(1.0) as usize
+}
+
+// Glyph placed on a line, in pixels from the top of the line at the pen origin
+pub(crate) struct PlacedGlyph<'a> {
+    c: char,
+    // Left, top, right and bottom edges, or `None` for glyphs without any pixels
+    bounds: Option<(i32, i32, i32, i32)>,
+    glyph: GlyphRef<'a>,
+}
+enum GlyphRef<'a> {
+    /// Rasterized glyph of a TTF or OTF font
+    Vector(Arc<RasterGlyph>),
+    /// Bitmap, and how many pixels wide and tall each of its pixels is drawn
+    Bitmap(&'a Bitmap, usize),
+}
+impl PlacedGlyph<'_> {
+    // Call `put` for every lit pixel
+    pub(crate) fn draw(&self, mut put: impl FnMut(i32, i32)) {
+        let Some((left, top, ..)) = self.bounds else {
+            return;
+        };
+        match &self.glyph {
+            GlyphRef::Vector(glyph) => {
+                let bitmap = &glyph.bitmap;
+                for y in 0..bitmap.h {
+                    for x in 0..bitmap.w {
+                        if bitmap.data[x + y * bitmap.w] {
+                            put(left + x as i32, top + y as i32);
+                        }
+                    }
+                }
+            }
+            GlyphRef::Bitmap(bitmap, scale) => {
+                for y in 0..bitmap.h * scale {
+                    for x in 0..bitmap.w * scale {
+                        if bitmap.data[x / scale + y / scale * bitmap.w] {
+                            put(left + x as i32, top + y as i32);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Horizontal pixel extent of glyphs, from the leftmost pixel or the origin, whichever is further left
+fn extent(glyphs: &[PlacedGlyph]) -> (i32, i32) {
+    glyphs
+        .iter()
+        .filter_map(|g| g.bounds)
+        .fold((0, 0), |(min, max), (left, _, right, _)| {
+            (min.min(left), max.max(right))
+        })
+}
+
+// Size of TTF and OTF fonts when none is given
+const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+// Glyphs of TTF and OTF fonts are rasterized at this many horizontal and vertical positions within a pixel
+const SUBPIXELS: f32 = 4.0;
+// Number of rasterized glyphs to keep, which is enough for a few fonts and sizes of most scripts
+const GLYPH_CACHE_SIZE: usize = 1024;
+
+// Rasterized glyph, in pixels from its rounded position
+pub(crate) struct RasterGlyph {
+    bitmap: Bitmap,
+    left: i32,
+    top: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    face: usize,
+    // `f32` bits, so the key can be hashed
+    size: u32,
+    id: u16,
+    subpixel: (u8, u8),
+    threshold: u32,
+}
+
+/// Renders text with a font, and any number of fallback fonts that are used for characters that the font has no
+/// glyph for.
+pub struct TextRenderer {
+    // Never empty
+    faces: Vec<Face>,
+    style: TextStyle,
+    // Rasterized glyphs of vector faces, so that text that is drawn again only has to be laid out
+    glyph_cache: Mutex<LruCache<GlyphKey, Arc<RasterGlyph>>>,
+}
+impl TextRenderer {
+    /// Load a TTF, OTF, BDF or PCF font, where the format is detected from the contents. PCF fonts may also be
+    /// gzipped. `size` is only used by TTF and OTF fonts, since bitmap fonts are drawn at their own size.
+    pub fn load_from_file(path: &PathBuf, size: Option<f32>) -> anyhow::Result<Self> {
+        let data = std::fs::read(path)?;
+        if BitmapFont::detect(&data) {
+            return Ok(Self::from_face(Face::Bitmap(BitmapFont::parse(&data)?)));
+        }
+        let Some(face) = Face::vector(data, size.unwrap_or(DEFAULT_FONT_SIZE)) else {
+            bail!("Failed to load font");
+        };
+        Ok(Self::from_face(face))
+    }
+    pub fn new_pixel_operator() -> Self {
+        let data = include_bytes!("../fonts/PixelOperator.ttf").to_vec();
+        Self::from_face(Face::vector(data, DEFAULT_FONT_SIZE).unwrap())
+    }
+    fn from_face(face: Face) -> Self {
+        Self {
+            faces: vec![face],
+            style: TextStyle::default(),
+            glyph_cache: Mutex::new(LruCache::new(NonZeroUsize::new(GLYPH_CACHE_SIZE).unwrap())),
+        }
+    }
+    /// Use the fonts of `fallback` for characters that the current fonts have no glyph for.
+    pub fn with_fallback(mut self, fallback: TextRenderer) -> Self {
+        self.faces.extend(fallback.faces);
+        self
+    }
+    pub fn with_style(self, style: TextStyle) -> Self {
+        Self { style, ..self }
+    }
+    pub fn style(&self) -> TextStyle {
+        self.style
+    }
+    // Baseline shared by all fonts, so that they line up when mixed
+    pub(crate) fn ascent(&self, zoom: f32) -> f32 {
+        self.faces.iter().map(|f| f.ascent_descent(zoom).0).fold(0.0, f32::max)
+    }
+    pub(crate) fn descent(&self, zoom: f32) -> f32 {
+        self.faces.iter().map(|f| f.ascent_descent(zoom).1).fold(0.0, f32::max)
+    }
+    // First font with a glyph for `c`, and its index
+    fn face_for(&self, c: char) -> (usize, &Face) {
+        self.faces
+            .iter()
+            .enumerate()
+            .find(|(_, face)| face.has_glyph(c))
+            .unwrap_or((0, &self.faces[0]))
+    }
+    // Height of lines that fits the tallest ascent and descent of all fonts
+    pub(crate) fn natural_line_height(&self) -> usize {
+        (self.ascent(1.0) + self.descent(1.0)).ceil() as usize
+    }
+    /// Height of lines, from the style or fitting the tallest ascent and descent of all fonts.
+    pub fn line_height(&self) -> usize {
+        self.style.line_height.unwrap_or_else(|| self.natural_line_height())
+    }
+    // Distance from the top of a line to the baseline, where the fonts are centered in lines with a custom height
+    pub(crate) fn baseline(&self) -> f32 {
+        self.ascent(1.0) + (self.line_height() as f32 - self.natural_line_height() as f32) / 2.0
+    }
+    /// Draw each line of the text to a bitmap that is as wide as the line and as tall as the line height.
+    pub fn render_lines(&self, text: &str) -> Vec<Bitmap> {
+        text.replace('\r', "")
+            .split('\n')
+            .map(|line| {
+                let glyphs = self.glyphs(line);
+                let (min, max) = extent(&glyphs);
+                // Leave room for the halo
+                let (left, right) = self.style.halo.pad();
+                let (min, max) = (min - left, max + right);
+                let mut bitmap = Bitmap::new((max - min) as usize, self.line_height(), false);
+                for glyph in &glyphs {
+                    glyph.draw(|x, y| bitmap.set((x - min) as isize, y as isize, true));
+                }
+                bitmap
+            })
+            .collect()
+    }
+
+    /// Pixels that text rendered by this renderer covers, which is the text itself and its halo. `None` without a
+    /// halo, since the text then only needs to be drawn on top of what's below.
+    pub fn halo_mask(&self, bitmap: &Bitmap) -> Option<Bitmap> {
+        let offsets = self.style.halo.offsets();
+        if offsets.is_empty() {
+            return None;
+        }
+        let mut mask = bitmap.clone();
+        for y in 0..bitmap.h as isize {
+            for x in 0..bitmap.w as isize {
+                if bitmap.get(x, y) {
+                    for (dx, dy) in offsets {
+                        mask.set(x + dx, y + dy, true);
+                    }
+                }
+            }
+        }
+        Some(mask)
+    }
+
+    // Glyphs of a line in visual order
+    fn glyphs(&self, line: &str) -> Vec<PlacedGlyph<'_>> {
+        self.place_line(line, 1.0, self.baseline(), &mut 0.0)
+    }
+
+    // Glyphs of text zoomed by `zoom`, with the baseline `baseline` pixels below the top of the line, starting at and
+    // advancing the pen
+    pub(crate) fn place_line(&self, line: &str, zoom: f32, baseline: f32, pen: &mut f32) -> Vec<PlacedGlyph<'_>> {
+        #[cfg(feature = "shaping")]
+        let runs = shape::visual_runs(line);
+        #[cfg(not(feature = "shaping"))]
+        let runs = [(0..line.len(), false)];
+        let mut glyphs = vec![];
+        for (run, rtl) in runs {
+            // Split into parts that use the same font
+            let mut parts: Vec<(usize, Range<usize>)> = vec![];
+            for (i, c) in line[run.clone()].char_indices() {
+                let (face, end) = (self.face_for(c).0, run.start + i + c.len_utf8());
+                match parts.last_mut() {
+                    Some((last, range)) if *last == face => range.end = end,
+                    _ => parts.push((face, run.start + i..end)),
+                }
+            }
+            if rtl {
+                parts.reverse();
+            }
+            for (face, range) in parts {
+                self.place(face, &line[range], rtl, (zoom, baseline), pen, &mut glyphs);
+            }
+        }
+        glyphs
+    }
+
+    // Rasterize a glyph of a vector face at a position, or take it from the cache. The position is rounded to the
+    // nearest subpixel, so that glyphs at nearby positions share their bitmap.
+    fn raster(&self, face: usize, glyph: ScaledGlyph<'_>, pos: Point<f32>, c: char) -> PlacedGlyph<'static> {
+        let (x, y) = ((pos.x * SUBPIXELS).round(), (pos.y * SUBPIXELS).round());
+        let (px, py) = ((x / SUBPIXELS).floor(), (y / SUBPIXELS).floor());
+        let (sx, sy) = (x - px * SUBPIXELS, y - py * SUBPIXELS);
+        let key = GlyphKey {
+            face,
+            size: glyph.scale().y.to_bits(),
+            id: glyph.id().0,
+            subpixel: (sx as u8, sy as u8),
+            threshold: self.style.threshold.to_bits(),
+        };
+        let raster = self.glyph_cache.lock().get(&key).cloned();
+        let raster = raster.unwrap_or_else(|| {
+            let glyph = glyph.positioned(point(sx / SUBPIXELS, sy / SUBPIXELS));
+            let raster = match glyph.pixel_bounding_box() {
+                Some(bb) => {
+                    let mut bitmap = Bitmap::new(bb.width() as usize, bb.height() as usize, false);
+                    glyph.draw(|x, y, v| {
+                        if v > self.style.threshold {
+                            bitmap.set(x as isize, y as isize, true);
+                        }
+                    });
+                    RasterGlyph {
+                        bitmap,
+                        left: bb.min.x,
+                        top: bb.min.y,
+                    }
+                }
+                None => RasterGlyph {
+                    bitmap: Bitmap::new(0, 0, false),
+                    left: 0,
+                    top: 0,
+                },
+            };
+            let raster = Arc::new(raster);
+            self.glyph_cache.lock().put(key, raster.clone());
+            raster
+        });
+        let (left, top) = (px as i32 + raster.left, py as i32 + raster.top);
+        let (w, h) = (raster.bitmap.w as i32, raster.bitmap.h as i32);
+        PlacedGlyph {
+            c,
+            bounds: (w > 0 && h > 0).then_some((left, top, left + w, top + h)),
+            glyph: GlyphRef::Vector(raster),
+        }
+    }
+
+    // Place the glyphs of text that uses a single font and direction, advancing the pen
+    fn place<'a>(
+        &'a self,
+        face_index: usize,
+        text: &str,
+        rtl: bool,
+        (zoom, ascent): (f32, f32),
+        pen: &mut f32,
+        out: &mut Vec<PlacedGlyph<'a>>,
+    ) {
+        let tracking = self.style.tracking as f32;
+        match &self.faces[face_index] {
+            #[cfg(feature = "shaping")]
+            Face::Vector { data, size } => {
+                let VectorFont { font, shaper } = data.borrow_dependent();
+                let scale = Scale::uniform(size * zoom);
+                let v_metrics = font.v_metrics_unscaled();
+                let px_per_unit = scale.y / (v_metrics.ascent - v_metrics.descent);
+                for shaped in shape::shape(shaper, text, rtl) {
+                    let c = text[shaped.cluster..].chars().next().unwrap_or(' ');
+                    let glyph = font.glyph(rusttype::GlyphId(shaped.id)).scaled(scale);
+                    let pos = point(
+                        *pen + shaped.x_offset as f32 * px_per_unit,
+                        ascent - shaped.y_offset as f32 * px_per_unit,
+                    );
+                    *pen += shaped.x_advance as f32 * px_per_unit + tracking;
+                    out.push(self.raster(face_index, glyph, pos, c));
+                }
+            }
+            #[cfg(not(feature = "shaping"))]
+            Face::Vector { data, size } => {
+                let font = &data.borrow_dependent().font;
+                let scale = Scale::uniform(size * zoom);
+                let mut prev = None;
+                for c in text.chars() {
+                    let glyph = font.glyph(c).scaled(scale);
+                    if let Some(prev) = prev {
+                        *pen += font.pair_kerning(scale, prev, glyph.id());
+                    }
+                    prev = Some(glyph.id());
+                    let advance = glyph.h_metrics().advance_width;
+                    out.push(self.raster(face_index, glyph, point(*pen, ascent), c));
+                    *pen += advance + tracking;
+                }
+            }
+            Face::Bitmap(font) => {
+                let chars: Vec<char> = if rtl {
+                    text.chars().rev().collect()
+                } else {
+                    text.chars().collect()
+                };
+                let scale = bitmap_scale(zoom);
+                let s = scale as i32;
+                for c in chars {
+                    let Some(glyph) = font.glyph(c) else {
+                        continue;
+                    };
+                    let (w, h) = (glyph.bitmap.w as i32 * s, glyph.bitmap.h as i32 * s);
+                    let left = pen.round() as i32 + glyph.x * s;
+                    let top = ascent.round() as i32 - glyph.y * s - h;
+                    *pen += (glyph.advance * s) as f32 + tracking;
+                    out.push(PlacedGlyph {
+                        c,
+                        bounds: (w > 0 && h > 0).then_some((left, top, left + w, top + h)),
+                        glyph: GlyphRef::Bitmap(&glyph.bitmap, scale),
+                    });
+                }
+            }
+        }
+    }
+
+    fn line_width(&self, line: &str) -> usize {
+        let (min, max) = extent(&self.glyphs(line));
+        (max - min) as usize
+    }
+
+    fn fits(&self, line: &str, width: Option<usize>) -> bool {
+        width.is_none_or(|w| self.line_width(line) <= w)
+    }
+
+    fn ellipsis(&self) -> &'static str {
+        if self.faces.iter().any(|f| f.has_glyph('…')) {
+            "…"
+        } else {
+            "..."
+        }
+    }
+
+    // Cut off the end of a line until it fits with an ellipsis
+    fn ellipsize(&self, line: &str, width: Option<usize>) -> String {
+        let mut line = line.trim_end().to_string();
+        loop {
+            let cut = format!("{line}{}", self.ellipsis());
+            if line.is_empty() || self.fits(&cut, width) {
+                return cut;
+            }
+            line.pop();
+            line.truncate(line.trim_end().len());
+        }
+    }
+
+    // Break a paragraph into lines of at most `width`
+    fn wrap_paragraph(&self, paragraph: &str, width: usize, wrap: Wrap, out: &mut Vec<Line>) {
+        let mut line = String::new();
+        let push = |line: &mut String, out: &mut Vec<Line>| {
+            out.push(Line {
+                text: std::mem::take(line),
+                wrapped: true,
+            })
+        };
+        match wrap {
+            Wrap::None => line = paragraph.to_string(),
+            Wrap::Char => {
+                // Place one character at a time and keep track of how far the line reaches, rather than measuring the
+                // whole line again for every character. Kerning between the characters is left out.
+                let (mut pen, mut reach) = (0.0, (0, 0));
+                for c in paragraph.chars() {
+                    let mut buf = [0; 4];
+                    let c_str = &*c.encode_utf8(&mut buf);
+                    let mut next = pen;
+                    let (min, max) = extent(&self.place_line(c_str, 1.0, self.baseline(), &mut next));
+                    let (min, max) = (min.min(reach.0), max.max(reach.1));
+                    if max - min > width as i32 && !line.is_empty() {
+                        push(&mut line, out);
+                        next = 0.0;
+                        reach = extent(&self.place_line(c_str, 1.0, self.baseline(), &mut next));
+                    } else {
+                        reach = (min, max);
+                    }
+                    pen = next;
+                    line.push(c);
+                }
+            }
+            Wrap::Word => {
+                for word in paragraph.split_whitespace() {
+                    let joined = if line.is_empty() {
+                        word.to_string()
+                    } else {
+                        format!("{line} {word}")
+                    };
+                    if self.fits(&joined, Some(width)) {
+                        line = joined;
+                        continue;
+                    }
+                    if !line.is_empty() {
+                        push(&mut line, out);
+                    }
+                    if self.fits(word, Some(width)) {
+                        line = word.to_string();
+                    } else {
+                        // Break up words that are too long by themselves
+                        self.wrap_paragraph(word, width, Wrap::Char, out);
+                        line = out.pop().map(|l| l.text).unwrap_or_default();
+                    }
+                }
+            }
+        }
+        out.push(Line {
+            text: line,
+            wrapped: false,
+        });
+    }
+
+    pub(crate) fn layout(&self, text: &str, opts: &TextOptions) -> Vec<Line> {
+        let text = text.replace('\r', "");
+        let mut lines = vec![];
+        for paragraph in text.split('\n') {
+            match opts.width {
+                Some(width) => self.wrap_paragraph(paragraph, width, opts.wrap, &mut lines),
+                None => lines.push(Line {
+                    text: paragraph.to_string(),
+                    wrapped: false,
+                }),
+            }
+        }
+        if let Some(max_lines) = opts.max_lines
+            && lines.len() > max_lines
+        {
+            lines.truncate(max_lines);
+            if let Some(last) = lines.last_mut() {
+                last.text = self.ellipsize(&last.text, opts.width);
+                last.wrapped = false;
+            }
+        }
+        for line in &mut lines {
+            if !self.fits(&line.text, opts.width) {
+                line.text = self.ellipsize(&line.text, opts.width);
+            }
+        }
+        lines
+    }
+
+    /// Size of the box that `render` would draw the text in, without rasterizing it.
+    pub fn measure(&self, text: &str, opts: &TextOptions) -> (usize, usize) {
+        self.box_size(&self.layout(text, opts), opts)
+    }
+
+    // Size of the box for lines that are already laid out
+    fn box_size(&self, lines: &[Line], opts: &TextOptions) -> (usize, usize) {
+        let w = opts
+            .width
+            .unwrap_or_else(|| lines.iter().map(|l| self.line_width(&l.text)).max().unwrap_or(0));
+        (w, lines.len() * self.line_height())
+    }
+
+    /// Lay out and draw text in a box.
+    pub fn render(&self, text: &str, opts: &TextOptions) -> Bitmap {
+        let lines = self.layout(text, opts);
+        let (w, h) = self.box_size(&lines, opts);
+        let mut bitmap = Bitmap::new(w, h, false);
+        for (i, line) in lines.iter().enumerate() {
+            let glyphs = self.glyphs(&line.text);
+            let (min, max) = extent(&glyphs);
+            let space = w as i32 - (max - min);
+            let spaces = line.text.matches(' ').count() as i32;
+            let justify = opts.align == TextAlign::Justify && line.wrapped && spaces > 0;
+            let x = match opts.align {
+                TextAlign::Left | TextAlign::Justify => 0,
+                TextAlign::Center => space / 2,
+                TextAlign::Right => space,
+            } - min;
+            let y = (i * self.line_height()) as i32;
+            let mut spaces_before = 0;
+            for glyph in &glyphs {
+                if glyph.c == ' ' {
+                    spaces_before += 1;
+                }
+                let stretch = if justify { space * spaces_before / spaces } else { 0 };
+                glyph.draw(|px, py| bitmap.set((x + stretch + px) as isize, (y + py) as isize, true));
+            }
+        }
+        bitmap
+    }
+}
+
+struct TextLine {
+    bitmap: Bitmap,
+    mask: Option<Bitmap>,
+    scroll: ScrollState,
+}
+
+pub(crate) struct TextCache {
+    text: String,
+    font: Arc<TextRenderer>,
+    lines: Vec<TextLine>,
+}
+impl TextCache {
+    pub fn new(text: &str, font: &Arc<TextRenderer>) -> Self {
+        Self {
+            text: text.to_string(),
+            font: font.clone(),
+            lines: font
+                .render_lines(text)
+                .into_iter()
+                .map(|bitmap| TextLine {
+                    mask: font.halo_mask(&bitmap),
+                    bitmap,
+                    scroll: ScrollState::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Get the cache for `text`, rasterizing it if the text or font has changed.
+    pub fn update<'a>(cache: &'a mut Option<Self>, text: &str, font: &Arc<TextRenderer>) -> &'a mut Self {
+        if !cache
+            .as_ref()
+            .is_some_and(|c| c.text == text && Arc::ptr_eq(&c.font, font))
+        {
+            *cache = Some(Self::new(text, font));
+        }
+        cache.as_mut().unwrap()
+    }
+
+    pub fn render(
+        &mut self,
+        screen: &mut Bitmap,
+        align: TextAlign,
+        rect: Rect,
+        scroll: &ScrollParams,
+        offset: (isize, isize),
+        time: Instant,
+    ) {
+        let line_height = self.font.line_height() as isize;
+        let top = rect.y + (rect.h as isize - line_height * self.lines.len() as isize) / 2;
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            let y = top + i as isize * line_height;
+            let bitmap = &line.bitmap;
+            if bitmap.w > rect.w {
+                let params = ScrollParams {
+                    clip: Some(Rect {
+                        x: rect.x,
+                        y,
+                        w: rect.w,
+                        h: bitmap.h,
+                    }),
+                    ..*scroll
+                };
+                line.scroll
+                    .render(screen, (bitmap, line.mask.as_ref()), (rect.x, y), offset, &params, time);
+            } else {
+                let space = (rect.w - bitmap.w) as isize;
+                let x = rect.x
+                    + match align {
+                        TextAlign::Left | TextAlign::Justify => 0,
+                        TextAlign::Center => space / 2,
+                        TextAlign::Right => space,
+                    };
+                match &line.mask {
+                    Some(mask) => screen.blit_masked(bitmap, mask, x + offset.0, y + offset.1),
+                    None => screen.blit(bitmap, x + offset.0, y + offset.1, false),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn text_layout() {
+    let font = TextRenderer::new_pixel_operator();
+    let texts = |lines: Vec<Line>| lines.into_iter().map(|l| l.text).collect::<Vec<_>>();
+    let width = font.line_width("hello world");
+    let opts = TextOptions {
+        width: Some(width),
+        ..Default::default()
+    };
+    assert_eq!(
+        texts(font.layout("hello world hello world", &opts)),
+        ["hello world", "hello world"]
+    );
+    let long = "helloworldhelloworld";
+    let lines = texts(font.layout(long, &opts));
+    assert_eq!(lines.concat(), long);
+    assert!(lines.len() > 1 && lines.iter().all(|l| font.line_width(l) <= width));
+    // Lines are broken as late as possible
+    assert!(
+        lines
+            .windows(2)
+            .all(|l| font.line_width(&format!("{}{}", l[0], &l[1][..1])) > width)
+    );
+    let limited = TextOptions {
+        max_lines: Some(1),
+        ..opts
+    };
+    let lines = texts(font.layout("hello world hello world", &limited));
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].ends_with(font.ellipsis()) && font.line_width(&lines[0]) <= width);
+    assert_eq!(
+        font.measure("a\nb", &TextOptions::default()),
+        (font.line_width("a").max(font.line_width("b")), font.line_height() * 2)
+    );
+    let justified = TextOptions {
+        align: TextAlign::Justify,
+        width: Some(width + 10),
+        ..opts
+    };
+    let bitmap = font.render("hello world hello", &justified);
+    assert_eq!((bitmap.w, bitmap.h), (width + 10, font.line_height() * 2));
+    // The last pixel column of the first line is lit, since it's stretched to fill the box
+    assert!((0..font.line_height()).any(|y| bitmap.get((width + 9) as isize, y as isize)));
+}
+
+#[cfg(test)]
+#[test]
+fn font_fallback() {
+    let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 4 20 0 -4\nSTARTPROPERTIES 2\nFONT_ASCENT 16\nFONT_DESCENT 4\n\
+               ENDPROPERTIES\nCHARS 1\nSTARTCHAR a\nENCODING 12354\nDWIDTH 4 0\nBBX 4 2 0 0\nBITMAP\nF0\nF0\n\
+               ENDCHAR\nENDFONT\n";
+    let primary = TextRenderer::new_pixel_operator();
+    let (ascent, descent) = primary.faces[0].ascent_descent(1.0);
+    let font = primary.with_fallback(TextRenderer::from_face(Face::Bitmap(
+        BitmapFont::parse(bdf.as_bytes()).unwrap(),
+    )));
+    assert_eq!(
+        font.line_height(),
+        (ascent.max(16.0) + descent.max(4.0)).ceil() as usize
+    );
+    let glyphs = font.glyphs("aあ");
+    assert!(matches!(glyphs[0].glyph, GlyphRef::Vector(..)) && matches!(glyphs[1].glyph, GlyphRef::Bitmap(..)));
+    // Both sit on the same baseline
+    let baseline = font.ascent(1.0).round() as i32;
+    assert_eq!(glyphs[0].bounds.unwrap().3, baseline);
+    assert_eq!(glyphs[1].bounds.unwrap().3, baseline);
+}
+
+#[cfg(test)]
+#[test]
+fn text_style() {
+    let lit = |b: &Bitmap| b.data.iter().filter(|p| *p).count();
+    let plain = TextRenderer::new_pixel_operator();
+    let styled = TextRenderer::new_pixel_operator().with_style(TextStyle {
+        halo: Halo::Outline,
+        tracking: 2,
+        line_height: Some(30),
+        threshold: 0.5,
+    });
+    assert_eq!(styled.line_width("abc"), plain.line_width("abc") + 4);
+    let line = &styled.render_lines("abc")[0];
+    assert_eq!(line.h, 30);
+    // The text sits in the middle of the taller line
+    assert_eq!(
+        styled.baseline() - plain.baseline(),
+        (30 - plain.line_height()) as f32 / 2.0
+    );
+    // The outline covers the pixels around the text, which has room for it on both sides
+    let mask = styled.halo_mask(line).unwrap();
+    assert_eq!(line.w, styled.line_width("abc") + 2);
+    let leftmost = |b: &Bitmap| (0..b.w as isize).find(|x| (0..b.h as isize).any(|y| b.get(*x, y)));
+    assert!(lit(&mask) > lit(line) && leftmost(&mask).unwrap() + 1 == leftmost(line).unwrap());
+    assert!(plain.halo_mask(line).is_none());
+    let thin = TextRenderer::new_pixel_operator().with_style(TextStyle {
+        threshold: 0.99,
+        ..Default::default()
+    });
+    assert!(lit(&thin.render_lines("abc")[0]) <= lit(&plain.render_lines("abc")[0]));
+}
+
+#[cfg(test)]
+#[test]
+fn glyph_cache() {
+    let font = TextRenderer::new_pixel_operator();
+    let first = font.render_lines("aaaa");
+    // Every `a` is on a whole pixel, so they share a single bitmap
+    assert_eq!(font.glyph_cache.lock().len(), 1);
+    assert_eq!(font.render_lines("aaaa"), first);
+    assert_eq!(font.glyph_cache.lock().len(), 1);
+    // Glyphs are cached by their threshold as well
+    let thin = font.with_style(TextStyle {
+        threshold: 0.99,
+        ..Default::default()
+    });
+    thin.render_lines("a");
+    assert_eq!(thin.glyph_cache.lock().len(), 2);
+}
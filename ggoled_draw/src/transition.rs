@@ -0,0 +1,186 @@
+// Transitions between the previously shown screen and newly committed layers.
+
+use crate::dither::Rng;
+use ggoled_lib::Bitmap;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Easing curve mapping linear progress to eased progress, both in the range `0.0..=1.0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+}
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        }
+    }
+}
+
+/// Direction that the incoming screen (or the wipe edge) moves in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// The new screen slides in on top of the old one
+    Slide(Direction),
+    /// The new screen slides in and pushes the old one out
+    Push(Direction),
+    /// An edge moves across the screen, revealing the new screen behind it
+    Wipe(Direction),
+    /// Pixels switch to the new screen in random order
+    Dissolve,
+    /// A box grows from the center, revealing the new screen inside it
+    Iris,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration: Duration,
+    pub easing: Easing,
+}
+impl Transition {
+    pub fn new(kind: TransitionKind, duration: Duration) -> Self {
+        Self {
+            kind,
+            duration,
+            easing: Easing::default(),
+        }
+    }
+}
+
+pub(crate) struct ActiveTransition {
+    transition: Transition,
+    from: Bitmap,
+    start: Instant,
+    // Order in which pixels switch when dissolving
+    order: Vec<u32>,
+}
+impl ActiveTransition {
+    pub fn new(transition: Transition, from: Bitmap, start: Instant) -> Self {
+        let order = if transition.kind == TransitionKind::Dissolve {
+            let seed = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default();
+            let mut rng = Rng::new(seed);
+            let mut order: Vec<u32> = (0..(from.w * from.h) as u32).collect();
+            for i in (1..order.len()).rev() {
+                order.swap(i, rng.below(i + 1));
+            }
+            order
+        } else {
+            vec![]
+        };
+        Self {
+            transition,
+            from,
+            start,
+            order,
+        }
+    }
+
+    pub fn done(&self, time: Instant) -> bool {
+        time.duration_since(self.start) >= self.transition.duration
+    }
+
+    /// Mix the outgoing screen with the incoming screen `to` for the progress at `time`.
+    pub fn mix(&self, to: &Bitmap, time: Instant) -> Bitmap {
+        let (from, t) = (&self.from, &self.transition);
+        if from.w != to.w || from.h != to.h {
+            return to.clone();
+        }
+        let linear = time.duration_since(self.start).as_secs_f32() / t.duration.as_secs_f32().max(f32::EPSILON);
+        let p = t.easing.apply(linear);
+        let (w, h) = (to.w as isize, to.h as isize);
+        let (dx, dy) = (((w as f32) * p).round() as isize, ((h as f32) * p).round() as isize);
+        // Position of the incoming screen, and how far the outgoing one is pushed
+        let slide = |dir: Direction| match dir {
+            Direction::Left => ((w - dx, 0), (-dx, 0)),
+            Direction::Right => ((dx - w, 0), (dx, 0)),
+            Direction::Up => ((0, h - dy), (0, -dy)),
+            Direction::Down => ((0, dy - h), (0, dy)),
+        };
+        let select = |use_to: &dyn Fn(isize, isize) -> bool| {
+            let mut out = from.clone();
+            for y in 0..h {
+                for x in 0..w {
+                    if use_to(x, y) {
+                        let i = (x + y * w) as usize;
+                        out.data.set(i, to.data[i]);
+                    }
+                }
+            }
+            out
+        };
+        match t.kind {
+            TransitionKind::Slide(dir) => {
+                let ((tx, ty), _) = slide(dir);
+                let mut out = from.clone();
+                out.blit(to, tx, ty, true);
+                out
+            }
+            TransitionKind::Push(dir) => {
+                let ((tx, ty), (fx, fy)) = slide(dir);
+                let mut out = Bitmap::new(to.w, to.h, false);
+                out.blit(from, fx, fy, true);
+                out.blit(to, tx, ty, true);
+                out
+            }
+            TransitionKind::Wipe(dir) => select(&|x, y| match dir {
+                Direction::Left => x >= w - dx,
+                Direction::Right => x < dx,
+                Direction::Up => y >= h - dy,
+                Direction::Down => y < dy,
+            }),
+            TransitionKind::Dissolve => {
+                let mut out = from.clone();
+                let n = (self.order.len() as f32 * p).round() as usize;
+                for &i in &self.order[..n.min(self.order.len())] {
+                    out.data.set(i as usize, to.data[i as usize]);
+                }
+                out
+            }
+            TransitionKind::Iris => {
+                let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+                select(&|x, y| (x as f32 + 0.5 - cx).abs() <= cx * p && (y as f32 + 0.5 - cy).abs() <= cy * p)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn transition_endpoints() {
+    let from = Bitmap::new(16, 8, false);
+    let to = Bitmap::new(16, 8, true);
+    let start = Instant::now();
+    let duration = Duration::from_millis(100);
+    for kind in [
+        TransitionKind::Slide(Direction::Left),
+        TransitionKind::Push(Direction::Down),
+        TransitionKind::Wipe(Direction::Right),
+        TransitionKind::Dissolve,
+        TransitionKind::Iris,
+    ] {
+        let active = ActiveTransition::new(Transition::new(kind, duration), from.clone(), start);
+        assert_eq!(active.mix(&to, start), from, "{kind:?}");
+        assert_eq!(active.mix(&to, start + duration), to, "{kind:?}");
+    }
+}
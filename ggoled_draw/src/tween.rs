@@ -0,0 +1,149 @@
+// Keyframe timelines that move layers and toggle their visibility from the render thread.
+
+use crate::transition::Easing;
+use std::time::{Duration, Instant};
+
+/// Layer properties at a point in a timeline. Properties that are `None` are left alone.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Keyframe {
+    /// Time from the start of the timeline
+    pub at: Duration,
+    pub x: Option<isize>,
+    pub y: Option<isize>,
+    pub visible: Option<bool>,
+    /// Easing of the movement from the previous keyframe to this one
+    pub easing: Easing,
+}
+impl Keyframe {
+    pub fn position(at: Duration, x: isize, y: isize, easing: Easing) -> Self {
+        Self {
+            at,
+            x: Some(x),
+            y: Some(y),
+            visible: None,
+            easing,
+        }
+    }
+    pub fn visible(at: Duration, visible: bool) -> Self {
+        Self {
+            at,
+            visible: Some(visible),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Repeat {
+    /// Play once and keep the final values
+    #[default]
+    Once,
+    Loop,
+    /// Play forward and then backward, repeatedly
+    PingPong,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Timeline {
+    keyframes: Vec<Keyframe>,
+    pub repeat: Repeat,
+}
+impl Timeline {
+    pub fn new(repeat: Repeat) -> Self {
+        Self {
+            keyframes: vec![],
+            repeat,
+        }
+    }
+    /// Move from one position to another over `duration`.
+    pub fn tween(from: (isize, isize), to: (isize, isize), duration: Duration, easing: Easing) -> Self {
+        Self::new(Repeat::Once)
+            .keyframe(Keyframe::position(Duration::ZERO, from.0, from.1, easing))
+            .keyframe(Keyframe::position(duration, to.0, to.1, easing))
+    }
+    /// Add a keyframe. Keyframes can be added in any order.
+    pub fn keyframe(mut self, keyframe: Keyframe) -> Self {
+        let i = self.keyframes.partition_point(|k| k.at <= keyframe.at);
+        self.keyframes.insert(i, keyframe);
+        self
+    }
+    pub fn duration(&self) -> Duration {
+        self.keyframes.last().map(|k| k.at).unwrap_or_default()
+    }
+
+    // Map time since start to time within the timeline
+    fn local_time(&self, elapsed: Duration) -> Duration {
+        let duration = self.duration();
+        if duration.is_zero() {
+            return duration;
+        }
+        let cycles = elapsed.as_secs_f64() / duration.as_secs_f64();
+        let within = duration.mul_f64(cycles.fract());
+        match self.repeat {
+            Repeat::Once => elapsed.min(duration),
+            Repeat::Loop => within,
+            Repeat::PingPong if (cycles as u64).is_multiple_of(2) => within,
+            Repeat::PingPong => duration - within,
+        }
+    }
+
+    fn interpolate(&self, t: Duration, get: impl Fn(&Keyframe) -> Option<isize>) -> Option<isize> {
+        let mut set = self.keyframes.iter().filter_map(|k| Some((k, get(k)?)));
+        let (mut prev_at, mut prev) = set.next().map(|(k, v)| (k.at, v))?;
+        for (k, v) in set {
+            if k.at <= t {
+                (prev_at, prev) = (k.at, v);
+                continue;
+            }
+            if t <= prev_at {
+                break;
+            }
+            let span = (k.at - prev_at).as_secs_f32();
+            let p = k.easing.apply((t - prev_at).as_secs_f32() / span);
+            return Some(prev + ((v - prev) as f32 * p).round() as isize);
+        }
+        Some(prev)
+    }
+
+    /// Values at `elapsed` since the start, and whether the timeline has ended.
+    pub(crate) fn sample(&self, elapsed: Duration) -> (Option<isize>, Option<isize>, Option<bool>, bool) {
+        let t = self.local_time(elapsed);
+        let visible = self
+            .keyframes
+            .iter()
+            .filter(|k| k.visible.is_some())
+            .take_while(|k| k.at <= t)
+            .last()
+            .and_then(|k| k.visible);
+        (
+            self.interpolate(t, |k| k.x),
+            self.interpolate(t, |k| k.y),
+            visible,
+            self.repeat == Repeat::Once && elapsed >= self.duration(),
+        )
+    }
+}
+
+pub(crate) struct TimelineState {
+    pub timeline: Timeline,
+    // Set when first evaluated, so that timelines start in sync with the frame they are first shown in
+    pub start: Option<Instant>,
+}
+
+#[cfg(test)]
+#[test]
+fn timeline_sampling() {
+    let ms = Duration::from_millis;
+    let timeline = Timeline::tween((0, 10), (100, 10), ms(1000), Easing::Linear)
+        .keyframe(Keyframe::visible(ms(500), false))
+        .keyframe(Keyframe::position(ms(2000), 100, 50, Easing::Linear));
+    assert_eq!(timeline.sample(ms(250)), (Some(25), Some(10), None, false));
+    assert_eq!(timeline.sample(ms(1500)), (Some(100), Some(30), Some(false), false));
+    assert_eq!(timeline.sample(ms(3000)), (Some(100), Some(50), Some(false), true));
+    let pingpong = Timeline {
+        repeat: Repeat::PingPong,
+        ..timeline
+    };
+    assert_eq!(pingpong.sample(ms(2500)), (Some(100), Some(30), Some(false), false));
+    assert_eq!(pingpong.sample(ms(3750)), (Some(25), Some(10), None, false));
+}
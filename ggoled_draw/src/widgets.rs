@@ -0,0 +1,320 @@
+// Widgets that render values to bitmaps: progress bars, batteries, meters, sparklines and gauges.
+
+use crate::DrawLayer;
+use ggoled_lib::Bitmap;
+use std::{f32::consts::PI, sync::Arc};
+
+/// Pattern used for the filled part of a widget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Fill {
+    #[default]
+    Solid,
+    /// Every other pixel, for a lighter look
+    Checker,
+    /// Vertical lines with a gap of one pixel
+    Stripes,
+}
+impl Fill {
+    fn on(self, x: isize, y: isize) -> bool {
+        match self {
+            Fill::Solid => true,
+            Fill::Checker => (x + y) % 2 == 0,
+            Fill::Stripes => x % 2 == 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WidgetStyle {
+    /// Draw a 1px border with a 1px gap around the filled part
+    pub border: bool,
+    pub fill: Fill,
+}
+impl Default for WidgetStyle {
+    fn default() -> Self {
+        Self {
+            border: true,
+            fill: Fill::Solid,
+        }
+    }
+}
+
+fn outline(bitmap: &mut Bitmap, x: isize, y: isize, w: usize, h: usize) {
+    if w == 0 || h == 0 {
+        return;
+    }
+    let (x1, y1) = (x + w as isize - 1, y + h as isize - 1);
+    bitmap.draw_line(x, y, x1, y, true);
+    bitmap.draw_line(x, y1, x1, y1, true);
+    bitmap.draw_line(x, y, x, y1, true);
+    bitmap.draw_line(x1, y, x1, y1, true);
+}
+
+fn fill(bitmap: &mut Bitmap, x: isize, y: isize, w: usize, h: usize, fill: Fill) {
+    for py in y..y + h as isize {
+        for px in x..x + w as isize {
+            if fill.on(px, py) {
+                bitmap.set(px, py, true);
+            }
+        }
+    }
+}
+
+// Draw the border if enabled, and return the box inside it
+fn frame(
+    bitmap: &mut Bitmap,
+    x: isize,
+    y: isize,
+    w: usize,
+    h: usize,
+    style: &WidgetStyle,
+) -> (isize, isize, usize, usize) {
+    if style.border {
+        outline(bitmap, x, y, w, h);
+        (x + 2, y + 2, w.saturating_sub(4), h.saturating_sub(4))
+    } else {
+        (x, y, w, h)
+    }
+}
+
+/// Something that renders a value to a bitmap of a fixed size.
+pub trait Widget {
+    type Value: ?Sized;
+
+    fn size(&self) -> (usize, usize);
+
+    fn render(&self, value: &Self::Value) -> Bitmap;
+
+    /// An image layer showing the widget for `value`.
+    fn layer(&self, value: &Self::Value, x: isize, y: isize) -> DrawLayer {
+        DrawLayer::Image {
+            bitmap: Arc::new(self.render(value)),
+            x,
+            y,
+        }
+    }
+}
+
+/// Bar that fills up with a value in the range `0.0..=1.0`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressBar {
+    pub w: usize,
+    pub h: usize,
+    pub style: WidgetStyle,
+    /// Fill from the bottom instead of from the left
+    pub vertical: bool,
+}
+impl Widget for ProgressBar {
+    type Value = f32;
+    fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+    fn render(&self, value: &f32) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        let (x, y, w, h) = frame(&mut bitmap, 0, 0, self.w, self.h, &self.style);
+        let value = value.clamp(0.0, 1.0);
+        if self.vertical {
+            let filled = (h as f32 * value).round() as usize;
+            fill(&mut bitmap, x, y + (h - filled) as isize, w, filled, self.style.fill);
+        } else {
+            fill(
+                &mut bitmap,
+                x,
+                y,
+                (w as f32 * value).round() as usize,
+                h,
+                self.style.fill,
+            );
+        }
+        bitmap
+    }
+}
+
+/// Battery icon with a charge level in the range `0.0..=1.0`, shown as segments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Battery {
+    pub w: usize,
+    pub h: usize,
+    pub segments: usize,
+    pub style: WidgetStyle,
+}
+impl Widget for Battery {
+    type Value = f32;
+    fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+    fn render(&self, value: &f32) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        // Terminal nub on the right
+        let nub_w = (self.w / 16).max(2).min(self.w);
+        let body_w = self.w - nub_w;
+        let nub_h = self.h / 2;
+        bitmap.fill_rect(body_w as isize, ((self.h - nub_h) / 2) as isize, nub_w, nub_h, true);
+
+        let (x, y, w, h) = frame(&mut bitmap, 0, 0, body_w, self.h, &self.style);
+        let segments = self.segments.max(1);
+        let filled = (value.clamp(0.0, 1.0) * segments as f32).ceil() as usize;
+        for i in 0..filled {
+            let x0 = x + (i * (w + 1) / segments) as isize;
+            let x1 = x + ((i + 1) * (w + 1) / segments) as isize - 1;
+            fill(&mut bitmap, x0, y, (x1 - x0).max(0) as usize, h, self.style.fill);
+        }
+        bitmap
+    }
+}
+
+/// Rising bars like a volume or signal meter, lit up to a value in the range `0.0..=1.0`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Meter {
+    pub w: usize,
+    pub h: usize,
+    pub bars: usize,
+    pub style: WidgetStyle,
+}
+impl Widget for Meter {
+    type Value = f32;
+    fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+    fn render(&self, value: &f32) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        let bars = self.bars.max(1);
+        let lit = (value.clamp(0.0, 1.0) * bars as f32).round() as usize;
+        for i in 0..bars {
+            let x0 = (i * (self.w + 1) / bars) as isize;
+            let x1 = ((i + 1) * (self.w + 1) / bars) as isize - 1;
+            let (w, h) = ((x1 - x0).max(0) as usize, (self.h * (i + 1)).div_ceil(bars));
+            let y = (self.h - h) as isize;
+            if i < lit {
+                fill(&mut bitmap, x0, y, w, h, self.style.fill);
+            } else if self.style.border {
+                outline(&mut bitmap, x0, y, w, h);
+            }
+        }
+        bitmap
+    }
+}
+
+/// Line graph of a series, showing the most recent values that fit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sparkline {
+    pub w: usize,
+    pub h: usize,
+    /// Range of the graph, taken from the values if unset
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    /// Fill the area below the line
+    pub area: bool,
+    pub style: WidgetStyle,
+}
+impl Widget for Sparkline {
+    type Value = [f32];
+    fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+    fn render(&self, values: &[f32]) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        let (x, y, w, h) = frame(&mut bitmap, 0, 0, self.w, self.h, &self.style);
+        if w == 0 || h == 0 {
+            return bitmap;
+        }
+        let values = &values[values.len().saturating_sub(w)..];
+        let min = self
+            .min
+            .unwrap_or_else(|| values.iter().copied().fold(f32::INFINITY, f32::min));
+        let max = self
+            .max
+            .unwrap_or_else(|| values.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+        let range = (max - min).max(f32::EPSILON);
+        let bottom = y + h as isize - 1;
+        let points: Vec<(isize, isize)> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let level = ((v - min) / range).clamp(0.0, 1.0);
+                (x + i as isize, bottom - (level * (h - 1) as f32).round() as isize)
+            })
+            .collect();
+        for (i, &(px, py)) in points.iter().enumerate() {
+            if self.area {
+                fill(&mut bitmap, px, py, 1, (bottom - py + 1) as usize, self.style.fill);
+            }
+            let (nx, ny) = points.get(i + 1).copied().unwrap_or((px, py));
+            bitmap.draw_line(px, py, nx, ny, true);
+        }
+        bitmap
+    }
+}
+
+/// Half-circle gauge that fills clockwise with a value in the range `0.0..=1.0`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gauge {
+    pub w: usize,
+    pub h: usize,
+    /// Width of the arc
+    pub thickness: usize,
+    pub style: WidgetStyle,
+}
+impl Widget for Gauge {
+    type Value = f32;
+    fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+    fn render(&self, value: &f32) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.w, self.h, false);
+        let (cx, cy) = ((self.w as f32 - 1.0) / 2.0, self.h as f32 - 1.0);
+        let outer = (self.w as f32 / 2.0).min(self.h as f32) - 0.5;
+        let inner = (outer - self.thickness as f32).max(0.0);
+        let value = value.clamp(0.0, 1.0);
+        for py in 0..self.h as isize {
+            for px in 0..self.w as isize {
+                let (dx, dy) = (px as f32 - cx, cy - py as f32);
+                let dist = (dx * dx + dy * dy).sqrt();
+                let turned = 1.0 - dy.atan2(dx) / PI;
+                let edge = self.style.border && ((dist - outer).abs() < 0.5 || (dist - inner).abs() < 0.5);
+                let filled = dist >= inner && dist <= outer && turned <= value && self.style.fill.on(px, py);
+                if dist <= outer + 0.5 && (edge || filled) {
+                    bitmap.set(px, py, true);
+                }
+            }
+        }
+        bitmap
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn widget_fill() {
+    let count = |bitmap: &Bitmap| bitmap.data.iter().filter(|on| *on).count();
+    let bar = ProgressBar {
+        w: 24,
+        h: 8,
+        style: WidgetStyle::default(),
+        vertical: false,
+    };
+    // Border of 2 * 24 + 2 * 6 pixels, and half of the 20x4 inside
+    assert_eq!(count(&bar.render(&0.5)), 60 + 40);
+    assert_eq!(count(&bar.render(&2.0)), 60 + 80);
+    let meter = Meter {
+        w: 11,
+        h: 9,
+        bars: 3,
+        style: WidgetStyle {
+            border: false,
+            fill: Fill::Solid,
+        },
+    };
+    assert_eq!(count(&meter.render(&0.7)), 3 * 3 + 3 * 6);
+    let line = Sparkline {
+        w: 4,
+        h: 4,
+        min: None,
+        max: None,
+        area: true,
+        style: WidgetStyle {
+            border: false,
+            fill: Fill::Solid,
+        },
+    };
+    assert_eq!(count(&line.render(&[9.0, 0.0, 1.0, 2.0, 3.0])), 1 + 2 + 3 + 4);
+}
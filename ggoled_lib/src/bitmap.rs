@@ -1,6 +1,6 @@
 pub use bit_vec::BitVec;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bitmap {
     pub w: usize,
     pub h: usize,
@@ -45,8 +45,87 @@ impl Bitmap {
         }
     }
 
+    /// Blit another Bitmap onto this one, but only where `mask` is set, so that unset pixels of `other` within the mask
+    /// clear what's below them. `mask` must be the same size as `other`.
+    pub fn blit_masked(&mut self, other: &Bitmap, mask: &Bitmap, x: isize, y: isize) {
+        assert!(mask.w == other.w && mask.h == other.h);
+        for oy in 0..other.h {
+            for ox in 0..other.w {
+                let (sx, sy) = (ox as isize + x, oy as isize + y);
+                let oi = ox + oy * other.w;
+                if mask.data[oi] && sx >= 0 && sx < self.w as isize && sy >= 0 && sy < self.h as isize {
+                    self.data.set(sx as usize + sy as usize * self.w, other.data[oi]);
+                }
+            }
+        }
+    }
+
     /// Inverts all pixels in the bitmap.
     pub fn invert(&mut self) {
         self.data.negate();
     }
+
+    /// Get a pixel. Out of bounds pixels are unset.
+    pub fn get(&self, x: isize, y: isize) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.w
+            && (y as usize) < self.h
+            && self.data[x as usize + y as usize * self.w]
+    }
+
+    /// Set a pixel. Out of bounds pixels are ignored.
+    pub fn set(&mut self, x: isize, y: isize, on: bool) {
+        if x >= 0 && y >= 0 && (x as usize) < self.w && (y as usize) < self.h {
+            self.data.set(x as usize + y as usize * self.w, on);
+        }
+    }
+
+    /// Set all pixels in a rectangle, clipped to the bitmap.
+    pub fn fill_rect(&mut self, x: isize, y: isize, w: usize, h: usize, on: bool) {
+        for py in y.max(0)..(y + h as isize).min(self.h as isize) {
+            for px in x.max(0)..(x + w as isize).min(self.w as isize) {
+                self.data.set(px as usize + py as usize * self.w, on);
+            }
+        }
+    }
+
+    /// Draw a 1px line between two points, inclusive.
+    pub fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, on: bool) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.set(x, y, on);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draw the 1px outline of a circle.
+    pub fn draw_circle(&mut self, cx: isize, cy: isize, r: isize, on: bool) {
+        let (mut x, mut y, mut err) = (r, 0, 1 - r);
+        while x >= y {
+            for (px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.set(cx + px, cy + py, on);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
 }